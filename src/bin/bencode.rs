@@ -1,15 +1,1162 @@
-use bencode_rs::parse_bencode;
-use std::io::{self, BufReader};
+use anyhow::{anyhow, bail, Result};
+use sha1::{Digest, Sha1};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+const CONTEXT: usize = 16;
+const FOLLOW_INTERVAL_MS: u64 = 200;
+const MAX_DEPTH: usize = 1000;
+const MAX_PIECE_LENGTH: i64 = 1 << 30;
+const MAX_FILE_LENGTH: i64 = 1 << 50;
+
+/// A decoded value, dictionary keys in the order they appeared in the input.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(Vec<(Vec<u8>, Value)>),
+}
+
+fn write_bytes(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => write!(f, "{:?}", s),
+        Err(_) => {
+            write!(f, "<")?;
+            for b in bytes {
+                write!(f, "{:02x}", b)?;
+            }
+            write!(f, ">")
+        }
+    }
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(entries) => entries
+                .iter()
+                .find(|(k, _)| k == key.as_bytes())
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        self.bytes()
+            .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+    }
+
+    fn list(&self) -> &[Value] {
+        match self {
+            Value::List(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn at(&self, path: &[Step]) -> Option<&Value> {
+        path.iter()
+            .try_fold(self, |value, step| match (step, value) {
+                (Step::Key(key), Value::Dict(entries)) => entries
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, value)| value),
+                (Step::Index(i), Value::List(items)) => {
+                    let i = if *i < 0 { items.len() as i64 + i } else { *i };
+                    items.get(usize::try_from(i).ok()?)
+                }
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    Key(Vec<u8>),
+    Index(i64),
+}
+
+/// Parses paths like `info.files[0].path` or `.files[-1]`. Keys that contain
+/// dots or brackets can be quoted as `["a.b"]` and keys that aren't UTF-8
+/// written as `$hex:..` like in the JSON output. `.` alone is the whole value.
+fn parse_path(path: &str) -> Result<Vec<Step>> {
+    let mut steps = vec![];
+    let mut rest = path.strip_prefix('.').unwrap_or(path);
+
+    while !rest.is_empty() {
+        if let Some(bracket) = rest.strip_prefix('[') {
+            let end = if let Some(quoted) = bracket.strip_prefix('"') {
+                quoted.find("\"]").map(|i| i + 2)
+            } else {
+                bracket.find(']')
+            }
+            .ok_or_else(|| anyhow!("Unclosed [ in path '{}'", path))?;
+            let inner = &bracket[..end];
+            steps.push(if let Some(key) = inner.strip_prefix('"') {
+                Step::Key(bencode_key(&key[..key.len() - 1])?)
+            } else {
+                Step::Index(
+                    inner
+                        .trim()
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid index [{}] in path '{}'", inner, path))?,
+                )
+            });
+            rest = &bracket[end + 1..];
+        } else {
+            let end = rest.find(&['.', '['][..]).unwrap_or(rest.len());
+            if end == 0 {
+                bail!("Empty key in path '{}'", path);
+            }
+            steps.push(Step::Key(bencode_key(&rest[..end])?));
+            rest = &rest[end..];
+        }
+        if let Some(next) = rest.strip_prefix('.') {
+            if next.is_empty() {
+                bail!("Empty key in path '{}'", path);
+            }
+            rest = next;
+        }
+    }
+    Ok(steps)
+}
+
+struct Key<'a>(&'a [u8]);
+
+impl fmt::Display for Key<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_bytes(f, self.0)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Bytes(bytes) => write_bytes(f, bytes),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Dict(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_bytes(f, key)?;
+                    write!(f, ": {}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Malformed input at byte offset `pos`, with the bytes around it.
+#[derive(Debug)]
+struct DecodeError {
+    pos: usize,
+    message: String,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} at byte {}", self.message, self.pos)?;
+
+        let start = self.pos - self.before.len();
+        let bytes: Vec<u8> = self
+            .before
+            .iter()
+            .chain(self.after.iter())
+            .cloned()
+            .collect();
+        for (row, chunk) in bytes.chunks(CONTEXT).enumerate() {
+            let offset = start + row * CONTEXT;
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(f, "{:08x}  {:<48} |{}|", offset, hex.join(" "), text)?;
+            if self.pos >= offset && self.pos < offset + CONTEXT {
+                writeln!(f, "{:10}{}^^", "", "   ".repeat(self.pos - offset))?;
+            }
+        }
+        if self.after.is_empty() {
+            writeln!(f, "{:08x}  <end of input>", self.pos)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Reads back-to-back bencoded values keeping track of the byte offset.
+/// Deviations from the canonical encoding are collected into `issues` and
+/// the byte ranges of the values of an outermost dictionary into `spans`.
+struct Decoder<R: BufRead> {
+    reader: R,
+    pos: usize,
+    follow: bool,
+    recent: VecDeque<u8>,
+    issues: Vec<(usize, String)>,
+    depth: usize,
+    spans: Vec<(Vec<u8>, Range<usize>)>,
+}
+
+impl<R: BufRead> Decoder<R> {
+    fn new(reader: R, follow: bool) -> Decoder<R> {
+        Decoder {
+            reader,
+            pos: 0,
+            follow,
+            recent: VecDeque::with_capacity(CONTEXT * 2),
+            issues: vec![],
+            depth: 0,
+            spans: vec![],
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if let Some(&b) = buf.first() {
+                return Ok(Some(b));
+            }
+            if !self.follow {
+                return Ok(None);
+            }
+            // more may be appended to the file, wait for it
+            thread::sleep(Duration::from_millis(FOLLOW_INTERVAL_MS));
+        }
+    }
+
+    fn next(&mut self) -> Result<u8> {
+        match self.peek()? {
+            Some(b) => {
+                self.reader.consume(1);
+                self.pos += 1;
+                if self.recent.len() == CONTEXT * 2 {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(b);
+                Ok(b)
+            }
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn error(&mut self, message: &str) -> anyhow::Error {
+        let after = self
+            .reader
+            .fill_buf()
+            .map(|buf| buf.iter().take(CONTEXT).cloned().collect())
+            .unwrap_or_default();
+        anyhow::Error::new(DecodeError {
+            pos: self.pos,
+            message: message.to_string(),
+            before: self.recent.iter().cloned().collect(),
+            after,
+        })
+    }
+
+    fn number(&mut self, end: u8) -> Result<i64> {
+        let start = self.pos;
+        let mut digits = String::new();
+        loop {
+            match self.peek()? {
+                Some(b) if b == end => {
+                    self.next()?;
+                    break;
+                }
+                Some(b) if b.is_ascii_digit() || (b == b'-' && digits.is_empty()) => {
+                    digits.push(self.next()? as char);
+                }
+                _ => return Err(self.error(&format!("Expected a digit or '{}'", end as char))),
+            }
+        }
+        if digits == "-0" {
+            self.issues.push((start, "-0".into()));
+        } else if digits.trim_start_matches('-').starts_with('0') && digits.len() > 1 {
+            self.issues
+                .push((start, format!("leading zero in '{}'", digits)));
+        }
+        match digits.parse() {
+            Ok(n) => Ok(n),
+            Err(_) => Err(self.error(&format!("Invalid number '{}'", digits))),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.number(b':')?;
+        if len < 0 {
+            return Err(self.error("Negative string length"));
+        }
+        // the length comes from the input, let the buffer grow as bytes arrive
+        let mut bytes = vec![];
+        for _ in 0..len {
+            bytes.push(self.next()?);
+        }
+        Ok(bytes)
+    }
+
+    /// Keeps deeply nested input from overflowing the stack.
+    fn nest(&mut self) -> Result<()> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek()? {
+            Some(b'i') => {
+                self.next()?;
+                Ok(Value::Int(self.number(b'e')?))
+            }
+            Some(b'l') => {
+                self.next()?;
+                self.nest()?;
+                let mut items = vec![];
+                while self.peek()? != Some(b'e') {
+                    items.push(self.value()?);
+                }
+                self.next()?;
+                self.depth -= 1;
+                Ok(Value::List(items))
+            }
+            Some(b'd') => {
+                self.next()?;
+                self.nest()?;
+                let mut entries = vec![];
+                while self.peek()? != Some(b'e') {
+                    match self.peek()? {
+                        Some(b) if b.is_ascii_digit() => {}
+                        _ => return Err(self.error("Expected a string key")),
+                    }
+                    let at = self.pos;
+                    let key = self.bytes()?;
+                    if let Some((last, _)) = entries.last() {
+                        if key == *last {
+                            self.issues
+                                .push((at, format!("duplicate key {}", Key(&key))));
+                        } else if key < *last {
+                            self.issues
+                                .push((at, format!("unsorted key {}", Key(&key))));
+                        }
+                    }
+                    let start = self.pos;
+                    let value = self.value()?;
+                    if self.depth == 1 {
+                        self.spans.push((key.clone(), start..self.pos));
+                    }
+                    entries.push((key, value));
+                }
+                self.next()?;
+                self.depth -= 1;
+                Ok(Value::Dict(entries))
+            }
+            Some(b) if b.is_ascii_digit() => Ok(Value::Bytes(self.bytes()?)),
+            Some(b) => Err(self.error(&format!("Unexpected byte 0x{:02x}", b))),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    /// The next value and its offset, None at the end of input.
+    fn read(&mut self) -> Result<Option<(usize, Value)>> {
+        // captures are often newline separated
+        while let Some(b) = self.peek()? {
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            self.next()?;
+        }
+        if self.peek()?.is_none() {
+            return Ok(None);
+        }
+        self.spans.clear();
+        let offset = self.pos;
+        Ok(Some((offset, self.value()?)))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        bail!("Invalid hex '{}'", s);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex '{}'", s)))
+        .collect()
+}
+
+fn json_key(key: &[u8]) -> String {
+    match std::str::from_utf8(key) {
+        Ok(key) if key.starts_with('$') => format!("${}", key),
+        Ok(key) => key.to_string(),
+        Err(_) => format!("$hex:{}", hex(key)),
+    }
+}
+
+fn bencode_key(key: &str) -> Result<Vec<u8>> {
+    if let Some(bytes) = key.strip_prefix("$hex:") {
+        unhex(bytes)
+    } else {
+        Ok(key.strip_prefix('$').unwrap_or(key).as_bytes().to_vec())
+    }
+}
+
+/// Byte strings become JSON strings when they are UTF-8 and `{"$hex": ".."}`
+/// otherwise. Keys that aren't UTF-8 become `"$hex:.."` and keys starting
+/// with `$` get another `$` in front, so that the markers can't be mistaken
+/// for data on the way back.
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Int(n) => serde_json::Value::from(*n),
+        Value::Bytes(bytes) => match std::str::from_utf8(bytes) {
+            Ok(s) => serde_json::Value::String(s.to_string()),
+            Err(_) => serde_json::json!({ "$hex": hex(bytes) }),
+        },
+        Value::List(items) => items.iter().map(to_json).collect(),
+        Value::Dict(entries) => {
+            let mut object = serde_json::Map::new();
+            for (key, value) in entries {
+                object.insert(json_key(key), to_json(value));
+            }
+            serde_json::Value::Object(object)
+        }
+    }
+}
+
+fn from_json(json: &serde_json::Value) -> Result<Value> {
+    match json {
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Ok(Value::Int(n)),
+            None => bail!("Bencode has only integers, not {}", n),
+        },
+        serde_json::Value::String(s) => Ok(Value::Bytes(s.as_bytes().to_vec())),
+        serde_json::Value::Array(items) => Ok(Value::List(
+            items.iter().map(from_json).collect::<Result<_>>()?,
+        )),
+        serde_json::Value::Object(object) => {
+            if let (1, Some(serde_json::Value::String(bytes))) = (object.len(), object.get("$hex"))
+            {
+                return Ok(Value::Bytes(unhex(bytes)?));
+            }
+            let mut entries = vec![];
+            for (key, value) in object {
+                entries.push((bencode_key(key)?, from_json(value)?));
+            }
+            Ok(Value::Dict(entries))
+        }
+        json => bail!("Bencode has no {}", json),
+    }
+}
+
+/// Canonical bencode, i.e. with dictionary keys sorted.
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Int(n) => out.extend(format!("i{}e", n).as_bytes()),
+        Value::Bytes(bytes) => {
+            out.extend(format!("{}:", bytes.len()).as_bytes());
+            out.extend(bytes);
+        }
+        Value::List(items) => {
+            out.push(b'l');
+            for item in items {
+                encode(item, out);
+            }
+            out.push(b'e');
+        }
+        Value::Dict(entries) => {
+            let mut entries: Vec<&(Vec<u8>, Value)> = entries.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            out.push(b'd');
+            for (key, value) in entries {
+                encode(&Value::Bytes(key.clone()), out);
+                encode(value, out);
+            }
+            out.push(b'e');
+        }
+    }
+}
+
+struct TorrentFile {
+    path: PathBuf,
+    length: u64,
+}
+
+/// The parts of a v1 `.torrent` that are shown and verified.
+struct Torrent {
+    name: String,
+    info_hash: String,
+    trackers: Vec<String>,
+    piece_length: u64,
+    pieces: Vec<Vec<u8>>,
+    files: Vec<TorrentFile>,
+    single_file: bool,
+}
+
+impl Torrent {
+    fn read(path: &Path) -> Result<Torrent> {
+        let data = std::fs::read(path)?;
+        let mut decoder = Decoder::new(&data[..], false);
+        let (_, torrent) = decoder
+            .read()?
+            .ok_or_else(|| anyhow!("Empty torrent file"))?;
+        if !matches!(torrent, Value::Dict(_)) {
+            bail!("Torrent file isn't a dictionary");
+        }
+        // spans are only recorded for the entries of the top-level dictionary
+        let info_span = decoder
+            .spans
+            .iter()
+            .find(|(key, _)| key == b"info")
+            .map(|(_, span)| span.clone());
+        let (info, info_span) = match (torrent.get("info"), info_span) {
+            (Some(info @ Value::Dict(_)), Some(span)) => (info, span),
+            _ => bail!("No info dictionary"),
+        };
+
+        let name = info.get("name").and_then(Value::text).unwrap_or_default();
+        let piece_length = info
+            .get("piece length")
+            .and_then(Value::int)
+            .filter(|n| *n > 0 && *n <= MAX_PIECE_LENGTH)
+            .ok_or_else(|| anyhow!("No valid piece length"))? as u64;
+        let pieces = info
+            .get("pieces")
+            .and_then(Value::bytes)
+            .filter(|pieces| pieces.len().is_multiple_of(20))
+            .ok_or_else(|| anyhow!("No piece hashes"))?
+            .chunks(20)
+            .map(|hash| hash.to_vec())
+            .collect();
+
+        let mut trackers = vec![];
+        for tier in torrent.get("announce-list").map(Value::list).unwrap_or(&[]) {
+            trackers.extend(tier.list().iter().filter_map(Value::text));
+        }
+        if let Some(announce) = torrent.get("announce").and_then(Value::text) {
+            if !trackers.contains(&announce) {
+                trackers.insert(0, announce);
+            }
+        }
+
+        let single_file = info.get("files").is_none();
+        let length = |file: &Value| {
+            file.get("length")
+                .and_then(Value::int)
+                .filter(|n| *n >= 0 && *n <= MAX_FILE_LENGTH)
+                .map(|n| n as u64)
+                .ok_or_else(|| anyhow!("No valid file length"))
+        };
+        let files = match info.get("files") {
+            None => vec![TorrentFile {
+                path: PathBuf::from(&name),
+                length: length(info)?,
+            }],
+            Some(files) => files
+                .list()
+                .iter()
+                .map(|file| {
+                    Ok(TorrentFile {
+                        path: file
+                            .get("path")
+                            .map(Value::list)
+                            .unwrap_or(&[])
+                            .iter()
+                            .filter_map(Value::text)
+                            .collect(),
+                        length: length(file)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        };
+
+        Ok(Torrent {
+            name,
+            info_hash: hex(&Sha1::digest(&data[info_span])),
+            trackers,
+            piece_length,
+            pieces,
+            files,
+            single_file,
+        })
+    }
+
+    fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.length).sum()
+    }
+
+    fn print(&self) {
+        println!("Name:         {}", self.name);
+        println!("Info-hash:    {}", self.info_hash);
+        for (i, tracker) in self.trackers.iter().enumerate() {
+            println!("{:14}{}", if i == 0 { "Trackers:" } else { "" }, tracker);
+        }
+        println!("Piece length: {}", size(self.piece_length));
+        println!("Pieces:       {}", self.pieces.len());
+        println!("Total size:   {}", size(self.total_size()));
+        println!("Files:");
+        for file in self.files.iter() {
+            println!("  {:>12}  {}", file.length, file.path.display());
+        }
+    }
+
+    /// Hashes the pieces of the files under `dir`, missing or short files
+    /// read as zeros. Returns the indices of the pieces that don't match.
+    fn verify(&self, dir: &Path) -> Result<Vec<usize>> {
+        let root = if self.single_file {
+            dir.to_path_buf()
+        } else {
+            dir.join(&self.name)
+        };
+        let mut data: Box<dyn Read> = Box::new(io::empty());
+        for file in self.files.iter() {
+            let content: Box<dyn Read> = match File::open(root.join(&file.path)) {
+                Ok(f) => Box::new(f.take(file.length).chain(io::repeat(0)).take(file.length)),
+                Err(_) => {
+                    eprintln!("Missing {}", root.join(&file.path).display());
+                    Box::new(io::repeat(0).take(file.length))
+                }
+            };
+            data = Box::new(data.chain(content));
+        }
+
+        let mut bad = vec![];
+        let mut piece = vec![];
+        for (i, hash) in self.pieces.iter().enumerate() {
+            piece.clear();
+            (&mut data)
+                .take(self.piece_length)
+                .read_to_end(&mut piece)?;
+            if Sha1::digest(&piece).as_slice() != &hash[..] {
+                bad.push(i);
+            }
+        }
+        Ok(bad)
+    }
+}
+
+fn size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {} ({} bytes)", value, units[unit], bytes)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Convert bencoded values to JSON, one per line
+    ToJson {
+        /// Pretty-print the JSON
+        #[structopt(long)]
+        pretty: bool,
+
+        /// Input file [default: stdin]
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Convert JSON values to canonical bencode
+    FromJson {
+        /// Input file [default: stdin]
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Print the value at a path like info.files[0].path from every input value.
+    /// Strings are printed as is and other values as JSON unless --bencode or
+    /// --json is given. Exits non-zero when no value had the path.
+    Get {
+        path: String,
+
+        /// Print the value bencoded
+        #[structopt(long, conflicts_with = "json")]
+        bencode: bool,
+
+        /// Print the value as JSON
+        #[structopt(long)]
+        json: bool,
+
+        /// Input file [default: stdin]
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// List the keys of the dictionary at a path, '.' for the top level
+    Keys {
+        path: String,
+
+        /// Print the keys as a JSON array
+        #[structopt(long)]
+        json: bool,
+
+        /// Input file [default: stdin]
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Show the contents and the info-hash of a .torrent file
+    Torrent {
+        /// Check the pieces of the downloaded files in this directory
+        #[structopt(long, parse(from_os_str))]
+        verify: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "bencode", about = "Decode back-to-back bencoded values")]
+struct Opt {
+    /// Keep waiting for more input at the end of the file, like tail -f
+    #[structopt(short, long, global = true)]
+    follow: bool,
+
+    /// Report input that isn't canonical bencode: unsorted or duplicate keys,
+    /// leading zeros and -0. Exits non-zero when there is any.
+    #[structopt(long, global = true)]
+    canonical: bool,
+
+    /// Input file [default: stdin]
+    #[structopt(parse(from_os_str))]
+    file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+fn open(path: Option<&Path>) -> Result<Box<dyn BufRead>> {
+    match path {
+        Some(path) => match File::open(path) {
+            Ok(file) => Ok(Box::new(BufReader::new(file))),
+            Err(e) => bail!("Unable to open {}: {}", path.display(), e),
+        },
+        None => Ok(Box::new(BufReader::new(io::stdin()))),
+    }
+}
+
+/// Decodes every value from `reader` passing it to `handler` with its offset.
+/// Returns false when `canonical` is set and some value wasn't canonical.
+fn decode(
+    reader: Box<dyn BufRead>,
+    opt: &Opt,
+    handler: &mut dyn FnMut(usize, &Value) -> Result<()>,
+) -> Result<bool> {
+    let mut decoder = Decoder::new(reader, opt.follow);
+    let mut canonical = true;
+
+    while let Some((offset, value)) = decoder.read()? {
+        handler(offset, &value)?;
+        // drained after every value so --follow doesn't keep them all
+        for (pos, issue) in decoder.issues.drain(..) {
+            if opt.canonical {
+                eprintln!("Not canonical: {} at byte {}", issue, pos);
+                canonical = false;
+            }
+        }
+    }
+    Ok(canonical)
+}
+
+fn run(opt: &Opt) -> Result<bool> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    match &opt.cmd {
+        None => decode(open(opt.file.as_deref())?, opt, &mut |offset, value| {
+            writeln!(out, "{:>8} {}", offset, value)?;
+            Ok(out.flush()?)
+        }),
+        Some(Command::ToJson { pretty, file }) => {
+            decode(open(file.as_deref())?, opt, &mut |_, value| {
+                let json = to_json(value);
+                if *pretty {
+                    writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+                } else {
+                    writeln!(out, "{}", json)?;
+                }
+                Ok(out.flush()?)
+            })
+        }
+        Some(Command::FromJson { file }) => {
+            let reader = open(file.as_deref())?;
+            for json in
+                serde_json::Deserializer::from_reader(reader).into_iter::<serde_json::Value>()
+            {
+                let mut bytes = vec![];
+                encode(&from_json(&json?)?, &mut bytes);
+                out.write_all(&bytes)?;
+            }
+            out.flush()?;
+            Ok(true)
+        }
+        Some(Command::Get {
+            path,
+            bencode,
+            json,
+            file,
+        }) => {
+            let path = parse_path(path)?;
+            let mut found = false;
+            let canonical = decode(open(file.as_deref())?, opt, &mut |_, value| {
+                let value = match value.at(&path) {
+                    Some(value) => value,
+                    None => return Ok(()),
+                };
+                found = true;
+                match value {
+                    _ if *bencode => {
+                        let mut bytes = vec![];
+                        encode(value, &mut bytes);
+                        out.write_all(&bytes)?;
+                    }
+                    Value::Bytes(bytes) if !*json => {
+                        out.write_all(bytes)?;
+                        writeln!(out)?;
+                    }
+                    value => writeln!(out, "{}", to_json(value))?,
+                }
+                Ok(out.flush()?)
+            })?;
+            Ok(canonical && found)
+        }
+        Some(Command::Keys { path, json, file }) => {
+            let path = parse_path(path)?;
+            let mut found = false;
+            let canonical = decode(open(file.as_deref())?, opt, &mut |_, value| {
+                let keys: Vec<String> = match value.at(&path) {
+                    Some(Value::Dict(entries)) => {
+                        entries.iter().map(|(k, _)| json_key(k)).collect()
+                    }
+                    _ => return Ok(()),
+                };
+                found = true;
+                if *json {
+                    writeln!(out, "{}", serde_json::Value::from(keys))?;
+                } else {
+                    for key in keys {
+                        writeln!(out, "{}", key)?;
+                    }
+                }
+                Ok(out.flush()?)
+            })?;
+            Ok(canonical && found)
+        }
+        Some(Command::Torrent { verify, file }) => {
+            let torrent = Torrent::read(file)?;
+            torrent.print();
+            match verify {
+                Some(dir) => {
+                    let bad = torrent.verify(dir)?;
+                    println!(
+                        "Verified:     {}/{} pieces OK",
+                        torrent.pieces.len() - bad.len(),
+                        torrent.pieces.len()
+                    );
+                    if !bad.is_empty() {
+                        let bad: Vec<String> = bad.iter().map(|i| i.to_string()).collect();
+                        println!("Bad pieces:   {}", bad.join(" "));
+                    }
+                    Ok(bad.is_empty())
+                }
+                None => Ok(true),
+            }
+        }
+    }
+}
 
 fn main() {
-    let mut reader = BufReader::new(io::stdin());
+    let opt = Opt::from_args();
+
+    match run(&opt) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprint!("Error: {}", e);
+            if e.downcast_ref::<DecodeError>().is_none() {
+                eprintln!();
+            }
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    match parse_bencode(&mut reader) {
-        Ok(Some(val)) => {
-            println!("Parsed string: {}", val.to_string());
-            println!("Generate bencode: {}", val.to_bencode());
+    fn decode_all(input: &[u8]) -> Result<Vec<(usize, Value)>> {
+        let mut decoder = Decoder::new(input, false);
+        let mut values = vec![];
+        while let Some(value) = decoder.read()? {
+            values.push(value);
         }
-        Ok(None) => (),
-        Err(e) => panic!("Error: {} ", e),
+        Ok(values)
+    }
+
+    fn error(input: &[u8]) -> String {
+        decode_all(input).unwrap_err().to_string()
+    }
+
+    fn issues(input: &[u8]) -> Vec<(usize, String)> {
+        let mut decoder = Decoder::new(input, false);
+        while decoder.read().unwrap().is_some() {}
+        decoder.issues
+    }
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut out = vec![];
+        encode(value, &mut out);
+        out
+    }
+
+    #[test]
+    fn decoder() {
+        assert_eq!(
+            decode_all(b"i-42e\n4:spam l1:ai0ee d1:xle1:ydee").unwrap(),
+            vec![
+                (0, Value::Int(-42)),
+                (6, bytes("spam")),
+                (13, Value::List(vec![bytes("a"), Value::Int(0)])),
+                (
+                    22,
+                    Value::Dict(vec![
+                        (b"x".to_vec(), Value::List(vec![])),
+                        (b"y".to_vec(), Value::Dict(vec![])),
+                    ])
+                ),
+            ]
+        );
+        assert_eq!(
+            decode_all(b"3:\xff\x00a").unwrap(),
+            vec![(0, Value::Bytes(vec![0xff, 0, b'a']))]
+        );
+        assert!(decode_all(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(
+            error(b"d3:fooi1e3:barxe"),
+            "Unexpected byte 0x78 at byte 14\n\
+             00000000  64 33 3a 66 6f 6f 69 31 65 33 3a 62 61 72 78 65  |d3:fooi1e3:barxe|\n\
+             \x20                                                   ^^\n"
+        );
+        assert_eq!(
+            error(b"i12"),
+            "Expected a digit or 'e' at byte 3\n\
+             00000000  69 31 32                                         |i12|\n\
+             \x20                  ^^\n\
+             00000003  <end of input>\n"
+        );
+        // the window shows at most 32 bytes before the error
+        let long = error(b"l4:spam40:0123456789abcdefghijklmnopqrstuvwxyz0123");
+        let lines: Vec<&str> = long.lines().collect();
+        assert_eq!(lines[0], "Unexpected end of input at byte 50");
+        assert!(lines[1].starts_with("00000012  38 39 61 62 63"));
+        assert_eq!(lines[3], "00000032  <end of input>");
+        assert!(error(b"di1ei2ee").starts_with("Expected a string key at byte 1\n"));
+        assert!(error(b"1:").starts_with("Unexpected end of input at byte 2\n"));
+        assert!(error(b"-1:").starts_with("Unexpected byte 0x2d at byte 0\n"));
+        assert!(error(b"i99999999999999999999e").starts_with("Invalid number"));
+    }
+
+    #[test]
+    fn depth() {
+        // the size of the main thread's stack, which the binary decodes on
+        let main = thread::Builder::new().stack_size(8 << 20);
+        let test = || {
+            let nested = |n| [vec![b'l'; n], vec![b'e'; n]].concat();
+            assert!(decode_all(&nested(MAX_DEPTH)).is_ok());
+            assert!(error(&nested(MAX_DEPTH + 1))
+                .starts_with(&format!("Nested too deeply at byte {}\n", MAX_DEPTH + 1)));
+        };
+        main.spawn(test).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn canonical() {
+        assert!(issues(b"d1:ai-1e1:bi10ee l0:i0ee").is_empty());
+        assert_eq!(
+            issues(b"i-0e i007e d1:bi0e1:ai0e1:ai0ee"),
+            vec![
+                (1, "-0".to_string()),
+                (6, "leading zero in '007'".to_string()),
+                (18, "unsorted key \"a\"".to_string()),
+                (24, "duplicate key \"a\"".to_string()),
+            ]
+        );
+        assert_eq!(issues(b"02:ab")[0], (0, "leading zero in '02'".to_string()));
+
+        let check = |args: &[&str], input: &'static [u8]| {
+            let opt = Opt::from_iter(args);
+            decode(Box::new(input), &opt, &mut |_, _| Ok(())).unwrap()
+        };
+        assert!(check(&["bencode", "--canonical"], b"d1:ai1e1:bi2ee"));
+        assert!(!check(&["bencode", "--canonical"], b"i1e d1:bi2e1:ai1ee"));
+        assert!(check(&["bencode"], b"d1:bi2e1:ai1ee"));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let input: &[u8] = b"d1:$i1e2:$$i2e4:$hexd4:$hex2:00e1:xl3:\xff\x00ai-3e0:e2:\xc3\xa9de\
+                             4:\xfe\xff\x00\x01le1:zd4:$hex2:zzee";
+        let (_, value) = decode_all(input).unwrap().remove(0);
+        let json = to_json(&value);
+        assert_eq!(
+            json.to_string(),
+            r#"{"$$":1,"$$$":2,"$$hex":{"$$hex":"00"},"$hex:feff0001":[],"x":[{"$hex":"ff0061"},-3,""],"z":{"$$hex":"zz"},"é":{}}"#
+        );
+        let back = from_json(&serde_json::from_str(&json.to_string()).unwrap()).unwrap();
+        assert_eq!(encoded(&back), encoded(&value));
+        assert_eq!(
+            from_json(&serde_json::json!({"$hex": "6869"})).unwrap(),
+            bytes("hi")
+        );
+        assert!(from_json(&serde_json::json!(1.5)).is_err());
+        assert!(from_json(&serde_json::json!(true)).is_err());
+        assert!(from_json(&serde_json::json!({"$hex": "6"})).is_err());
+    }
+
+    #[test]
+    fn paths() {
+        let key = |k: &str| Step::Key(k.as_bytes().to_vec());
+        assert_eq!(
+            parse_path("info.files[0].path").unwrap(),
+            vec![key("info"), key("files"), Step::Index(0), key("path")]
+        );
+        assert_eq!(
+            parse_path(r#".files[-1]["a.b"][$hex:ff]"#)
+                .unwrap_err()
+                .to_string(),
+            "Invalid index [$hex:ff] in path '.files[-1][\"a.b\"][$hex:ff]'"
+        );
+        assert_eq!(
+            parse_path(r#".files[-1]["a.b"].$hex:ff.$$x"#).unwrap(),
+            vec![
+                key("files"),
+                Step::Index(-1),
+                key("a.b"),
+                Step::Key(vec![0xff]),
+                key("$x"),
+            ]
+        );
+        assert!(parse_path(".").unwrap().is_empty());
+        assert!(parse_path("a..b").is_err());
+        assert!(parse_path("a.").is_err());
+        assert!(parse_path("a[0").is_err());
+
+        let (_, value) = decode_all(b"d5:filesl1:a1:b1:ce3:a.bi1ee")
+            .unwrap()
+            .remove(0);
+        let at = |path| value.at(&parse_path(path).unwrap()).cloned();
+        assert_eq!(at("files[0]"), Some(bytes("a")));
+        assert_eq!(at("files[-1]"), Some(bytes("c")));
+        assert_eq!(at("files[3]"), None);
+        assert_eq!(at("files[-4]"), None);
+        assert_eq!(at(r#"["a.b"]"#), Some(Value::Int(1)));
+        assert_eq!(at("a.b"), None);
+        assert_eq!(at("files.x"), None);
+        assert_eq!(at("."), Some(value.clone()));
+    }
+
+    #[test]
+    fn torrent() {
+        let dir = std::env::temp_dir().join(format!("bencode-test-{}", process::id()));
+        let content = b"hello world";
+        let pieces: Vec<u8> = content
+            .chunks(4)
+            .flat_map(|piece| Sha1::digest(piece).to_vec())
+            .collect();
+        // keys out of order, the info-hash is of the info dictionary as written
+        let info = [
+            &b"d5:filesld6:lengthi5e4:pathl1:aeed6:lengthi6e4:pathl3:sub1:beee\
+               4:name1:t12:piece lengthi4e6:pieces60:"[..],
+            &pieces,
+            b"e",
+        ]
+        .concat();
+        let torrent = [
+            &b"d8:announce5:http:13:announce-listll5:udp:1el5:http:ee4:info"[..],
+            &info,
+            b"e",
+        ]
+        .concat();
+        std::fs::create_dir_all(dir.join("t/sub")).unwrap();
+        std::fs::write(dir.join("x.torrent"), &torrent).unwrap();
+
+        let parsed = Torrent::read(&dir.join("x.torrent")).unwrap();
+        assert_eq!(parsed.info_hash, hex(&Sha1::digest(&info)));
+        assert_eq!(parsed.name, "t");
+        assert_eq!(parsed.trackers, vec!["udp:1", "http:"]);
+        assert_eq!(parsed.pieces.len(), 3);
+        assert_eq!(parsed.total_size(), 11);
+        assert_eq!(parsed.files[1].path, Path::new("sub/b"));
+
+        std::fs::write(dir.join("t/a"), &content[..5]).unwrap();
+        assert_eq!(parsed.verify(&dir).unwrap(), vec![1, 2]);
+        std::fs::write(dir.join("t/sub/b"), &content[5..]).unwrap();
+        assert!(parsed.verify(&dir).unwrap().is_empty());
+        std::fs::write(dir.join("t/sub/b"), b" worl").unwrap();
+        assert_eq!(parsed.verify(&dir).unwrap(), vec![2]);
+
+        std::fs::write(dir.join("bad.torrent"), b"d4:infod6:pieces3:abcee").unwrap();
+        assert!(Torrent::read(&dir.join("bad.torrent")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,24 +1,882 @@
-use anyhow::Result;
-use edn::parser::Parser;
+use anyhow::{bail, Result};
+use sandbox::edn_reader::{
+    self, character, escape, inst, is_keyword_name, is_number, is_symbol, is_uuid, qualify,
+    unescape, Coll, Form, Node, Number, Reader, SyntaxError,
+};
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use structopt::StructOpt;
 
-fn main() -> Result<()> {
-    // let str = "(defn sum [xs]
-    //              (reduce + 0 xs))
-    //            (println (sum [1 2 3 4 5]))";
+fn is_trailing_comment(form: &Form) -> bool {
+    matches!(form.node, Node::Comment(_)) && form.newlines == 0
+}
+
+/// The form on one line, or None when it contains comments.
+fn inline(form: &Form) -> Option<String> {
+    let items = |forms: &[Form]| {
+        forms
+            .iter()
+            .map(inline)
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(" "))
+    };
+    Some(match &form.node {
+        Node::Atom(text) => text.clone(),
+        Node::Comment(_) => return None,
+        Node::Coll(coll, forms) => format!("{}{}{}", coll.open(), items(forms)?, coll.close()),
+        Node::NsMap(ns, forms) => format!("#:{}{{{}}}", ns, items(forms)?),
+        Node::Tagged(tag, forms) => format!("#{} {}", tag, items(forms)?),
+        Node::Discard(forms) => format!("#_{}", items(forms)?),
+    })
+}
+
+/// The column where `text` ends when it starts at column `indent`.
+fn end_col(text: &str, indent: usize) -> usize {
+    match text.rfind('\n') {
+        Some(i) => text[i + 1..].chars().count(),
+        None => indent + text.chars().count(),
+    }
+}
+
+fn newline(out: &mut String, col: usize, blank: bool) {
+    if blank {
+        out.push('\n');
+    }
+    out.push('\n');
+    out.push_str(&" ".repeat(col));
+}
+
+/// A map entry with the comments and discarded forms before it. Ones between
+/// the key and the value are moved before the key.
+struct Entry<'a> {
+    before: Vec<&'a Form>,
+    key: &'a Form,
+    value: &'a Form,
+    comment: Option<&'a Form>,
+}
+
+impl<'a> Entry<'a> {
+    fn forms(&self) -> impl Iterator<Item = &'a Form> + '_ {
+        self.before
+            .iter()
+            .copied()
+            .chain(vec![self.key, self.value])
+            .chain(self.comment)
+    }
+}
+
+/// Splits the forms of a map into entries and the forms after the last one.
+fn entries(forms: &[Form]) -> (Vec<Entry<'_>>, Vec<&Form>) {
+    let mut entries: Vec<Entry> = vec![];
+    let mut pending = vec![];
+    let mut key = None;
+
+    for form in forms {
+        if form.is_value() {
+            match key.take() {
+                None => key = Some(form),
+                Some(key) => entries.push(Entry {
+                    before: std::mem::take(&mut pending),
+                    key,
+                    value: form,
+                    comment: None,
+                }),
+            }
+        } else if is_trailing_comment(form) && key.is_none() && pending.is_empty() {
+            match entries.last_mut() {
+                Some(entry) if entry.comment.is_none() => entry.comment = Some(form),
+                _ => pending.push(form),
+            }
+        } else {
+            pending.push(form);
+        }
+    }
+    (entries, pending)
+}
+
+fn sort_keys(form: &Form) -> Form {
+    let mut form = form.clone();
+    match &mut form.node {
+        Node::Coll(_, forms)
+        | Node::NsMap(_, forms)
+        | Node::Tagged(_, forms)
+        | Node::Discard(forms) => {
+            for item in forms.iter_mut() {
+                *item = sort_keys(item);
+            }
+        }
+        _ => {}
+    }
+    if let Node::Coll(Coll::Map, forms) | Node::NsMap(_, forms) = &mut form.node {
+        let sorted = {
+            let (mut entries, rest) = entries(forms);
+            entries.sort_by_cached_key(|entry| entry.key.flat());
+            entries
+                .iter()
+                .flat_map(Entry::forms)
+                .chain(rest)
+                .cloned()
+                .collect()
+        };
+        *forms = sorted;
+    }
+    form
+}
+
+/// Lays out forms on lines of at most `width` columns where possible.
+/// Collections that don't fit get one item per line, with map values aligned
+/// and lists indented by two after the leading symbol and atoms that fit.
+struct Printer {
+    width: usize,
+}
+
+impl Printer {
+    fn document(&self, forms: &[Form]) -> String {
+        let mut out = String::new();
+        self.lines(&mut out, forms, 0, 0, true);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+
+    /// The form starting at column `indent`, later lines indented with spaces.
+    fn form(&self, form: &Form, indent: usize) -> String {
+        if let Some(text) = inline(form) {
+            if indent + text.chars().count() <= self.width {
+                return text;
+            }
+        }
+        let mut out = String::new();
+        match &form.node {
+            Node::Atom(text) | Node::Comment(text) => out.push_str(text),
+            Node::Coll(Coll::Map, forms) => self.map(&mut out, "{", forms, indent),
+            Node::NsMap(ns, forms) => self.map(&mut out, &format!("#:{}{{", ns), forms, indent),
+            Node::Coll(Coll::List, forms) => self.list(&mut out, forms, indent),
+            Node::Coll(coll, forms) => {
+                out.push_str(coll.open());
+                let col = indent + coll.open().len();
+                let comment = self.lines(&mut out, forms, indent, col, true);
+                self.close(&mut out, coll.close(), col, comment);
+            }
+            Node::Tagged(tag, forms) => {
+                out.push_str(&format!("#{} ", tag));
+                self.lines(
+                    &mut out,
+                    forms,
+                    indent,
+                    indent + tag.chars().count() + 2,
+                    true,
+                );
+            }
+            Node::Discard(forms) => {
+                out.push_str("#_");
+                self.lines(&mut out, forms, indent, indent + 2, true);
+            }
+        }
+        out
+    }
+
+    /// Puts forms on their own lines at column `col`, except for comments
+    /// that were at the end of the previous form's line and, if `continued`,
+    /// the first form. Returns true when the last form is a comment.
+    fn lines(
+        &self,
+        out: &mut String,
+        forms: &[Form],
+        indent: usize,
+        col: usize,
+        continued: bool,
+    ) -> bool {
+        for (i, form) in forms.iter().enumerate() {
+            if i > 0 && is_trailing_comment(form) {
+                out.push(' ');
+            } else if i > 0 || !continued {
+                newline(out, col, i > 0 && form.newlines > 1);
+            }
+            let text = self.form(form, end_col(out, indent));
+            out.push_str(&text);
+        }
+        matches!(
+            forms.last(),
+            Some(Form {
+                node: Node::Comment(_),
+                ..
+            })
+        )
+    }
+
+    fn close(&self, out: &mut String, close: char, col: usize, comment: bool) {
+        if comment {
+            newline(out, col, false);
+        }
+        out.push(close);
+    }
+
+    fn list(&self, out: &mut String, forms: &[Form], indent: usize) {
+        out.push('(');
+        let mut head = 0;
+        if let Some(Form {
+            node: Node::Atom(symbol),
+            ..
+        }) = forms.first()
+        {
+            out.push_str(symbol);
+            head = 1;
+            while let Some(Form {
+                node: Node::Atom(atom),
+                ..
+            }) = forms.get(head)
+            {
+                if end_col(out, indent) + 1 + atom.chars().count() > self.width
+                    || head == forms.len() - 1
+                {
+                    break;
+                }
+                out.push(' ');
+                out.push_str(atom);
+                head += 1;
+            }
+        }
+        let col = if head == 0 { indent + 1 } else { indent + 2 };
+        let comment = self.lines(out, &forms[head..], indent, col, head == 0);
+        self.close(out, ')', col, comment);
+    }
+
+    fn map(&self, out: &mut String, open: &str, forms: &[Form], indent: usize) {
+        out.push_str(open);
+        let col = indent + open.chars().count();
+        let (entries, rest) = entries(forms);
+        let key_width = entries
+            .iter()
+            .filter_map(|entry| inline(entry.key))
+            .map(|key| key.chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut first = true;
+        let mut comment = false;
+
+        for entry in entries.iter() {
+            for form in entry.before.iter() {
+                if !first {
+                    newline(out, col, form.newlines > 1);
+                }
+                out.push_str(&self.form(form, col));
+                first = false;
+            }
+            if !first {
+                newline(out, col, entry.before.is_empty() && entry.key.newlines > 1);
+            }
+            first = false;
+            let key = self.form(entry.key, col);
+            let value_col = if key.contains('\n') {
+                end_col(&key, col) + 1
+            } else {
+                col + key_width + 1
+            };
+            out.push_str(&key);
+            out.push_str(&" ".repeat(value_col - end_col(out, indent)));
+            out.push_str(&self.form(entry.value, value_col));
+            if let Some(Form {
+                node: Node::Comment(text),
+                ..
+            }) = entry.comment
+            {
+                out.push(' ');
+                out.push_str(text);
+            }
+            comment = entry.comment.is_some();
+        }
+        for form in rest {
+            if !first {
+                newline(out, col, form.newlines > 1);
+            }
+            out.push_str(&self.form(form, col));
+            first = false;
+            comment = matches!(form.node, Node::Comment(_));
+        }
+        self.close(out, '}', col, comment);
+    }
+}
+
+/// Numbers JSON can't hold keep their digits as a string.
+fn number(text: &str) -> serde_json::Value {
+    let n = match edn_reader::number(text) {
+        Some(Number::Int(n)) => Some(n.into()),
+        Some(Number::UInt(n)) => Some(n.into()),
+        Some(Number::Float(f)) => serde_json::Number::from_f64(f),
+        _ => None,
+    };
+    match n {
+        Some(n) => serde_json::Value::Number(n),
+        None => serde_json::Value::String(text.trim_end_matches(&['N', 'M'][..]).to_string()),
+    }
+}
+
+/// How EDN values map to JSON and back, see `edn to-json --help`.
+#[derive(StructOpt, Debug)]
+struct Mapping {
+    /// Keep the colon of keywords. Reading JSON, only strings starting with a
+    /// colon become keywords.
+    #[structopt(long)]
+    keyword_prefix: bool,
+
+    /// Key for the tag of tagged values
+    #[structopt(long, default_value = "#tag")]
+    tag_key: String,
+
+    /// Key for the value of tagged values
+    #[structopt(long, default_value = "#value")]
+    value_key: String,
+}
+
+impl Mapping {
+    fn keyword(&self, name: &str) -> String {
+        if self.keyword_prefix {
+            format!(":{}", name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    fn atom(&self, text: &str) -> serde_json::Value {
+        match text {
+            "nil" | "##Inf" | "##-Inf" | "##NaN" => serde_json::Value::Null,
+            "true" => serde_json::Value::Bool(true),
+            "false" => serde_json::Value::Bool(false),
+            _ if text.starts_with('"') => serde_json::Value::String(unescape(text)),
+            _ if text.starts_with('\\') => serde_json::Value::String(character(text).to_string()),
+            _ if text.starts_with(':') => serde_json::Value::String(self.keyword(&text[1..])),
+            _ if is_number(text) => number(text),
+            _ => serde_json::Value::String(text.to_string()),
+        }
+    }
+
+    fn key(&self, form: &Form, ns: Option<&str>) -> Result<String, SyntaxError> {
+        match &form.node {
+            Node::Atom(text) if text.starts_with(':') => Ok(self.keyword(&qualify(&text[1..], ns))),
+            Node::Atom(text)
+                if is_symbol(text) && !["nil", "true", "false"].contains(&text.as_str()) =>
+            {
+                Ok(qualify(text, ns))
+            }
+            _ => match self.json(form)? {
+                serde_json::Value::String(s) => Ok(s),
+                _ => Ok(form.flat()),
+            },
+        }
+    }
+
+    fn object(&self, forms: &[Form], ns: Option<&str>) -> Result<serde_json::Value, SyntaxError> {
+        let values: Vec<&Form> = forms.iter().filter(|form| form.is_value()).collect();
+        let mut object = serde_json::Map::new();
+        for pair in values.chunks(2) {
+            let key = self.key(pair[0], ns)?;
+            if object.insert(key.clone(), self.json(pair[1])?).is_some() {
+                return Err(SyntaxError {
+                    line: pair[0].line,
+                    col: pair[0].col,
+                    message: format!("Duplicate key \"{}\" in JSON", key),
+                });
+            }
+        }
+        Ok(serde_json::Value::Object(object))
+    }
+
+    fn json(&self, form: &Form) -> Result<serde_json::Value, SyntaxError> {
+        let error = |message: String| SyntaxError {
+            line: form.line,
+            col: form.col,
+            message,
+        };
+        Ok(match &form.node {
+            Node::Atom(text) => self.atom(text),
+            Node::Coll(Coll::Map, forms) => self.object(forms, None)?,
+            Node::NsMap(ns, forms) => self.object(forms, Some(ns))?,
+            Node::Coll(_, forms) => serde_json::Value::Array(
+                forms
+                    .iter()
+                    .filter(|form| form.is_value())
+                    .map(|form| self.json(form))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Node::Tagged(tag, forms) => {
+                let value = forms.last().unwrap();
+                match (tag.as_str(), &value.node) {
+                    ("inst", Node::Atom(text)) if text.starts_with('"') => {
+                        let text = unescape(text);
+                        match inst(&text) {
+                            Some(inst) => serde_json::Value::String(inst),
+                            None => return Err(error(format!("Invalid #inst \"{}\"", text))),
+                        }
+                    }
+                    ("uuid", Node::Atom(text)) if text.starts_with('"') => {
+                        let uuid = unescape(text);
+                        if !is_uuid(&uuid) {
+                            return Err(error(format!("Invalid #uuid \"{}\"", uuid)));
+                        }
+                        serde_json::Value::String(uuid.to_lowercase())
+                    }
+                    ("inst", _) | ("uuid", _) => {
+                        return Err(error(format!("#{} needs a string", tag)))
+                    }
+                    _ => {
+                        let mut object = serde_json::Map::new();
+                        object.insert(self.tag_key.clone(), tag.clone().into());
+                        object.insert(self.value_key.clone(), self.json(value)?);
+                        serde_json::Value::Object(object)
+                    }
+                }
+            }
+            Node::Discard(_) | Node::Comment(_) => serde_json::Value::Null,
+        })
+    }
+
+    fn edn(&self, json: &serde_json::Value) -> Form {
+        let node = match json {
+            serde_json::Value::Null => Node::Atom("nil".into()),
+            serde_json::Value::Bool(b) => Node::Atom(b.to_string()),
+            serde_json::Value::Number(n) => Node::Atom(n.to_string()),
+            serde_json::Value::String(s) => match s.strip_prefix(':') {
+                Some(name) if self.keyword_prefix && is_keyword_name(name) => Node::Atom(s.clone()),
+                _ => Node::Atom(escape(s)),
+            },
+            serde_json::Value::Array(items) => Node::Coll(
+                Coll::Vector,
+                items.iter().map(|item| self.edn(item)).collect(),
+            ),
+            serde_json::Value::Object(object) => {
+                let tag = object.get(&self.tag_key).and_then(|tag| tag.as_str());
+                match (tag, object.get(&self.value_key)) {
+                    (Some(tag), Some(value))
+                        if object.len() == 2
+                            && tag.starts_with(char::is_alphabetic)
+                            && is_symbol(tag) =>
+                    {
+                        Node::Tagged(tag.to_string(), vec![self.edn(value)])
+                    }
+                    _ => Node::Coll(
+                        Coll::Map,
+                        object
+                            .iter()
+                            .flat_map(|(key, value)| vec![self.edn_key(key), self.edn(value)])
+                            .collect(),
+                    ),
+                }
+            }
+        };
+        Form {
+            node,
+            line: 0,
+            col: 0,
+            newlines: 0,
+        }
+    }
 
-    let data = r#"{ 
-    "string" "John"
-    "int" 43
-    "vec" ["+43 1234567" "+44 2345678"] 
-    "map" {:key "val"}
+    fn edn_key(&self, key: &str) -> Form {
+        let name = if self.keyword_prefix {
+            key.strip_prefix(':')
+        } else {
+            Some(key)
+        };
+        let node = match name {
+            Some(name) if is_keyword_name(name) => Node::Atom(format!(":{}", name)),
+            _ => Node::Atom(escape(key)),
+        };
+        Form {
+            node,
+            line: 0,
+            col: 0,
+            newlines: 0,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Reprint EDN with consistent indentation, keeping comments and #_ forms
+    Fmt {
+        /// Maximum line width
+        #[structopt(long, default_value = "80")]
+        width: usize,
+
+        /// Sort the keys of maps
+        #[structopt(long)]
+        sort_keys: bool,
+
+        /// Only report the files that aren't formatted, exiting non-zero if any
+        #[structopt(long)]
+        check: bool,
+
+        /// Rewrite the files in place
+        #[structopt(short, long, conflicts_with = "check")]
+        write: bool,
+
+        /// Input files [default: stdin]
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+    /// Convert EDN to JSON, one value per line
+    ///
+    /// nil, booleans, numbers and strings become their JSON counterparts,
+    /// except ##Inf, ##-Inf and ##NaN that become null and integers too big
+    /// for JSON that become strings.
+    /// Characters, symbols and keywords become strings, keywords without the
+    /// colon unless --keyword-prefix is given.
+    /// Lists, vectors and sets become arrays.
+    /// Map keys that aren't strings, keywords or symbols become their EDN text.
+    /// Namespaced maps like #:ns{:a 1 :_/b 2} are expanded to {"ns/a": 1, "b": 2}.
+    /// #inst and #uuid become their RFC 3339 and UUID strings, partial
+    /// timestamps like #inst "2020-01" completed to 2020-01-01T00:00:00Z.
+    /// Keys that end up the same, like :a and "a", are an error.
+    /// Other tagged values like #my/tag 1 become {"#tag": "my/tag", "#value": 1}
+    /// with the keys given by --tag-key and --value-key.
+    /// Comments and #_ forms are left out.
+    #[structopt(verbatim_doc_comment)]
+    ToJson {
+        #[structopt(flatten)]
+        mapping: Mapping,
+
+        /// Pretty-print the JSON
+        #[structopt(long)]
+        pretty: bool,
+
+        /// Write YAML documents instead of JSON
+        #[structopt(long, conflicts_with = "pretty")]
+        yaml: bool,
+
+        /// Input files [default: stdin]
+        #[structopt(parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
+    /// Convert JSON to EDN
+    ///
+    /// The reverse of to-json.
+    /// Arrays become vectors.
+    /// Object keys become keywords when they are valid keyword names, or with
+    /// --keyword-prefix when they start with a colon, and strings otherwise.
+    /// With --keyword-prefix also strings like ":a" become keywords.
+    /// Objects with just the tag and the value keys become tagged values.
+    /// #inst and #uuid values stay strings.
+    #[structopt(verbatim_doc_comment)]
+    FromJson {
+        #[structopt(flatten)]
+        mapping: Mapping,
+
+        /// Read YAML documents instead of JSON
+        #[structopt(long)]
+        yaml: bool,
+
+        /// Input file [default: stdin]
+        #[structopt(parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "edn", about = "Check that files are valid EDN")]
+struct Opt {
+    /// Input files [default: stdin]
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+fn read_source(path: Option<&PathBuf>) -> Result<String> {
+    let mut src = String::new();
+    let result = match path {
+        Some(path) => fs::File::open(path).and_then(|mut file| file.read_to_string(&mut src)),
+        None => io::stdin().read_to_string(&mut src),
+    };
+    if let Err(e) = result {
+        bail!("{}", e);
+    }
+    Ok(src)
+}
+
+/// Formats the forms read from `src`. Returns false when checking and the
+/// source isn't formatted.
+fn fmt(
+    width: usize,
+    sort: bool,
+    check: bool,
+    write: bool,
+    path: Option<&PathBuf>,
+    src: &str,
+    forms: &[Form],
+) -> Result<bool> {
+    let formatted = if sort {
+        Printer { width }.document(&forms.iter().map(sort_keys).collect::<Vec<_>>())
+    } else {
+        Printer { width }.document(forms)
+    };
+
+    match path {
+        _ if check => Ok(formatted == src),
+        Some(path) if write => {
+            if formatted != src {
+                fs::write(path, formatted)?;
+            }
+            Ok(true)
+        }
+        None if write => bail!("Nothing to write to when reading stdin"),
+        _ => {
+            io::stdout().lock().write_all(formatted.as_bytes())?;
+            Ok(true)
+        }
+    }
+}
 
-    "mapns" #:ns{:key "val"}
-    "inst" #inst "1985-04-12T23:20:50.52Z"
-}"#;
+fn to_json(mapping: &Mapping, pretty: bool, yaml: bool, forms: &[Form]) -> Result<bool> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
 
-    let mut parser = Parser::new(data);
-    println!("{:?}", parser.read());
-    println!("{:?}", parser.read());
+    for form in forms.iter().filter(|form| form.is_value()) {
+        let json = mapping.json(form)?;
+        if yaml {
+            let doc = serde_yaml::to_string(&json)?;
+            write!(out, "{}", doc)?;
+            if !doc.ends_with('\n') {
+                writeln!(out)?;
+            }
+        } else if pretty {
+            writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+        } else {
+            writeln!(out, "{}", json)?;
+        }
+    }
+    Ok(true)
+}
 
+fn from_json(mapping: &Mapping, yaml: bool, src: &str) -> Result<()> {
+    let values: Vec<serde_json::Value> = if yaml {
+        serde_yaml::Deserializer::from_str(src)
+            .map(serde_json::Value::deserialize)
+            .collect::<Result<_, _>>()?
+    } else {
+        serde_json::Deserializer::from_str(src)
+            .into_iter::<serde_json::Value>()
+            .collect::<Result<_, _>>()?
+    };
+    let forms: Vec<Form> = values.iter().map(|value| mapping.edn(value)).collect();
+    let edn = Printer { width: 80 }.document(&forms);
+    io::stdout().lock().write_all(edn.as_bytes())?;
     Ok(())
 }
+
+/// Checks, formats or converts every input, reporting errors as
+/// `file:line:col: message`. Returns false when some input wasn't valid EDN
+/// or wasn't formatted.
+fn run(opt: &Opt) -> bool {
+    let files = match &opt.cmd {
+        Some(Command::Fmt { files, .. }) | Some(Command::ToJson { files, .. }) => files,
+        Some(Command::FromJson {
+            mapping,
+            yaml,
+            file,
+        }) => {
+            let name = file
+                .as_ref()
+                .map_or("<stdin>".into(), |path| path.display().to_string());
+            return match read_source(file.as_ref()).and_then(|src| from_json(mapping, *yaml, &src))
+            {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("{}: {}", name, e);
+                    false
+                }
+            };
+        }
+        None => &opt.files,
+    };
+    let inputs: Vec<Option<&PathBuf>> = if files.is_empty() {
+        vec![None]
+    } else {
+        files.iter().map(Some).collect()
+    };
+    let mut valid = true;
+
+    for path in inputs {
+        let name = path.map_or("<stdin>".into(), |path| path.display().to_string());
+        let src = match read_source(path) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                valid = false;
+                continue;
+            }
+        };
+        let forms = match Reader::new(&src).read_all() {
+            Ok(forms) => forms,
+            Err(e) => {
+                eprintln!("{}:{}", name, e);
+                valid = false;
+                continue;
+            }
+        };
+        let result = match &opt.cmd {
+            Some(Command::Fmt {
+                width,
+                sort_keys,
+                check,
+                write,
+                ..
+            }) => fmt(*width, *sort_keys, *check, *write, path, &src, &forms),
+            Some(Command::ToJson {
+                mapping,
+                pretty,
+                yaml,
+                ..
+            }) => to_json(mapping, *pretty, *yaml, &forms),
+            _ => Ok(true),
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("{}: not formatted", name);
+                valid = false;
+            }
+            Err(e) if e.is::<SyntaxError>() => {
+                eprintln!("{}:{}", name, e);
+                valid = false;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                valid = false;
+            }
+        }
+    }
+    valid
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if !run(&opt) {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(src: &str) -> Vec<Form> {
+        Reader::new(src).read_all().unwrap()
+    }
+
+    fn format(src: &str, width: usize) -> String {
+        Printer { width }.document(&read(src))
+    }
+
+    fn mapping(keyword_prefix: bool) -> Mapping {
+        Mapping {
+            keyword_prefix,
+            tag_key: "#tag".into(),
+            value_key: "#value".into(),
+        }
+    }
+
+    fn json(mapping: &Mapping, src: &str) -> String {
+        mapping.json(&read(src)[0]).unwrap().to_string()
+    }
+
+    fn edn(mapping: &Mapping, json: &str) -> String {
+        let value = serde_json::from_str(json).unwrap();
+        Printer { width: 80 }.document(&[mapping.edn(&value)])
+    }
+
+    #[test]
+    fn printer() {
+        let src = "; deps\n{:paths [\"src\"] ; sources\n :deps {org.clojure/clojure \
+                   {:mvn/version \"1.10.1\"} #_#_ a/b {:mvn/version \"1\"}}\n\n\n \
+                   :aliases {:test {:extra-paths [\"test\"]}}}\n(defn f [x] (inc x))";
+        let formatted = format(src, 60);
+        assert_eq!(
+            formatted,
+            "; deps\n\
+             {:paths   [\"src\"] ; sources\n \
+             :deps    {org.clojure/clojure {:mvn/version \"1.10.1\"}\n           \
+             #_#_a/b {:mvn/version \"1\"}}\n\n \
+             :aliases {:test {:extra-paths [\"test\"]}}}\n\
+             (defn f [x] (inc x))\n"
+        );
+        assert_eq!(format(&formatted, 60), formatted);
+        for width in &[10, 40, 80] {
+            let formatted = format(src, *width);
+            assert_eq!(format(&formatted, *width), formatted);
+        }
+    }
+
+    #[test]
+    fn sorted_keys_keep_their_comments() {
+        let sorted = sort_keys(&read("{:b 1 ; one\n :a #_x 2}")[0]);
+        let formatted = Printer { width: 80 }.document(&[sorted]);
+        assert_eq!(formatted, "{#_x\n :a 2\n :b 1 ; one\n }\n");
+        assert_eq!(format(&formatted, 80), formatted);
+    }
+
+    #[test]
+    fn to_json() {
+        let plain = mapping(false);
+        let prefixed = mapping(true);
+        assert_eq!(
+            json(&plain, "{:a/b 1, \"c\" nil, sym true, 3 \\x, [1] ##NaN}"),
+            r#"{"3":"x","[1]":null,"a/b":1,"c":null,"sym":true}"#
+        );
+        assert_eq!(
+            json(&prefixed, "#:x{:a :b, :_/c 1, :y/d 2}"),
+            r#"{":c":1,":x/a":":b",":y/d":2}"#
+        );
+        assert_eq!(
+            json(&plain, "[1N 18446744073709551616 1.5M 1e999 #{1}]"),
+            r#"[1,"18446744073709551616",1.5,"1e999",[1]]"#
+        );
+        assert_eq!(
+            json(
+                &plain,
+                "[#inst \"2020\" #uuid \"F81D4FAE-7DEC-11D0-A765-00A0C91E6BF6\"]"
+            ),
+            r#"["2020-01-01T00:00:00Z","f81d4fae-7dec-11d0-a765-00a0c91e6bf6"]"#
+        );
+        assert_eq!(
+            json(&plain, "#my/tag [1 #_2]"),
+            r##"{"#tag":"my/tag","#value":[1]}"##
+        );
+        let error = |src| plain.json(&read(src)[0]).unwrap_err().to_string();
+        assert_eq!(
+            error("{:a 1\n \"a\" 2}"),
+            "2:2: Duplicate key \"a\" in JSON"
+        );
+        assert_eq!(error("[#inst \"+1:00\"]"), "1:2: Invalid #inst \"+1:00\"");
+        assert_eq!(error("#uuid 1"), "1:1: #uuid needs a string");
+    }
+
+    #[test]
+    fn from_json() {
+        let plain = mapping(false);
+        let prefixed = mapping(true);
+        let json = r##"{"a": ":b", "c d": [1, 1.5, null], "#tag": "x"}"##;
+        assert_eq!(
+            edn(&plain, json),
+            "{\"#tag\" \"x\" :a \":b\" \"c d\" [1 1.5 nil]}\n"
+        );
+        assert_eq!(
+            edn(&prefixed, json),
+            "{\"#tag\" \"x\" \"a\" :b \"c d\" [1 1.5 nil]}\n"
+        );
+        assert_eq!(
+            edn(&plain, r##"{"#tag": "my/tag", "#value": {"\n": true}}"##),
+            "#my/tag {\"\\n\" true}\n"
+        );
+        assert_eq!(
+            edn(&plain, r##"{"#tag": "1", "#value": 2}"##),
+            "{\"#tag\" \"1\" \"#value\" 2}\n"
+        );
+    }
+}
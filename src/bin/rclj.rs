@@ -1,14 +1,72 @@
-use anyhow::{anyhow, bail, format_err, Result};
-use edn::parser::Parser;
+use anyhow::{anyhow, bail, Result};
+use rustyline::completion::Completer;
+use rustyline::config::{CompletionType, EditMode};
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
+use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Editor, EventContext, EventHandler, InputMode, KeyCode, KeyEvent,
+    Modifiers, Movement, RepeatCount,
+};
+use rustyline_derive::{Helper, Hinter, Validator};
+use sandbox::serde_edn;
+use serde::Deserialize;
+use std::borrow::Cow;
 use std::boxed::Box;
-use std::collections::{BTreeMap, HashMap};
+use std::cmp::min;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
 use std::io::BufReader;
-use std::io::{stderr, stdout, BufRead};
+use std::io::{stderr, stdout, BufRead, ErrorKind};
 use std::io::{Read, Write};
 use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use structopt::StructOpt;
+use tuikit::prelude::{
+    Attr, Canvas, Color, Draw, DrawResult, Effect, Event as TermEvent, HSplit, Key, Size, Term,
+    VSplit, Widget, Win,
+};
+
+const RECONNECT_ATTEMPTS: usize = 10;
+const RECONNECT_MAX_DELAY_MS: u64 = 8000;
+const INSPECT_PAGE: usize = 20;
+const INSPECT_SUMMARY_WIDTH: usize = 100;
+const WATCH_INTERVAL_MS: u64 = 500;
+const TUI_MAX_LINES: usize = 5000;
+const TUI_SCROLL_STEP: usize = 10;
+const TUI_POLL_MS: u64 = 100;
+const JACK_IN_TIMEOUT_MS: u64 = 300_000;
+
+/// Raised by the repl implementations when the server has gone away and the
+/// connection can not be used anymore.
+#[derive(Debug)]
+struct ConnectionLost(String);
+
+impl fmt::Display for ConnectionLost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "connection lost: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConnectionLost {}
+
+// Only errors on the REPL stream are wrapped, a failing pager or transcript
+// must not cost the session.
+fn connection_lost(e: impl fmt::Display) -> anyhow::Error {
+    ConnectionLost(e.to_string()).into()
+}
+
+fn is_connection_lost(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ConnectionLost>().is_some()
+}
 
 fn write_and_flush(w: &mut dyn Write, data: &str) -> Result<()> {
     w.write_all(data.as_bytes())?;
@@ -17,57 +75,416 @@ fn write_and_flush(w: &mut dyn Write, data: &str) -> Result<()> {
     Ok(())
 }
 
-fn get_value(key: &str, map: &BTreeMap<edn::Value, edn::Value>) -> Option<String> {
-    match map.get(&edn::Value::Keyword(key.into())) {
-        Some(edn::Value::String(value)) => {
-            return Some(value.into());
-        }
-        Some(edn::Value::Keyword(value)) => {
-            return Some(value.into());
-        }
-        Some(edn::Value::Boolean(value)) => {
-            return Some(value.to_string());
-        }
-        _ => None,
-    }
-}
-
 enum Response {
     Done(Option<String>),
     Exception(String),
     StdOut(String),
     StdErr(String),
+    Value(String),
+    Tap(String),
     Other(String),
 }
 
+enum Runtime {
+    Clj,
+    Cljs(String),
+}
+
+enum Command {
+    Cljs(String),
+    Clj,
+    Describe,
+    Save(String),
+    Timing,
+    Bench(usize, String),
+    Inspect(Option<String>),
+    More,
+    Watch(String),
+    Unwatch,
+    Test(Vec<String>),
+    In(String, String),
+    Ns(String),
+}
+
+fn parse_command(line: &str) -> Option<Command> {
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let name = parts.next()?;
+    let arg = parts.next().unwrap_or("").trim();
+
+    match name {
+        ":cljs" if !arg.is_empty() => Some(Command::Cljs(arg.into())),
+        ":clj" if arg.is_empty() => Some(Command::Clj),
+        ":describe" if arg.is_empty() => Some(Command::Describe),
+        ":save" if !arg.is_empty() => Some(Command::Save(arg.into())),
+        ":timing" if arg.is_empty() => Some(Command::Timing),
+        ":more" if arg.is_empty() => Some(Command::More),
+        ":watch" if !arg.is_empty() => Some(Command::Watch(arg.into())),
+        ":unwatch" if arg.is_empty() => Some(Command::Unwatch),
+        ":test" => Some(Command::Test(
+            arg.split_whitespace().map(String::from).collect(),
+        )),
+        ":in" => {
+            let mut parts = arg.splitn(2, char::is_whitespace);
+            let ns = parts.next().filter(|ns| !ns.is_empty())?;
+            let form = parts.next()?.trim();
+            Some(Command::In(ns.into(), form.into()))
+        }
+        ":ns" => Some(Command::Ns(arg.into())),
+        ":inspect" if arg.is_empty() => Some(Command::Inspect(None)),
+        ":inspect" => Some(Command::Inspect(Some(arg.into()))),
+        ":bench" => {
+            let mut parts = arg.splitn(2, char::is_whitespace);
+            let n = parts.next()?.parse::<usize>().ok().filter(|n| *n > 0)?;
+            let form = parts.next()?.trim();
+            Some(Command::Bench(n, form.into()))
+        }
+        _ => None,
+    }
+}
+
+// A plain build id starts a shadow-cljs build repl, a form is taken as a
+// repl-env for piggieback, e.g. ':cljs (cljs.repl.node/repl-env)'.
+fn cljs_repl_form(arg: &str) -> String {
+    if arg.starts_with('(') {
+        format!("(cider.piggieback/cljs-repl {})", arg)
+    } else {
+        format!(
+            "(shadow.cljs.devtools.api/repl :{})",
+            arg.trim_start_matches(':')
+        )
+    }
+}
+
+/// What the connected server told us about itself. `ops` is `None` when the
+/// server could not be asked, in which case every op is assumed to work.
+#[derive(Default)]
+struct Capabilities {
+    ops: Option<BTreeSet<String>>,
+    versions: BTreeMap<String, String>,
+}
+
+impl Capabilities {
+    fn supports(&self, op: &str) -> bool {
+        self.ops.as_ref().is_none_or(|ops| ops.contains(op))
+    }
+}
+
 trait Repl {
     fn send(&mut self, s: &str) -> Result<()>;
     fn get_ns(&self) -> String;
     fn recv(&mut self) -> Result<Response>;
     fn quit(&mut self) -> Result<()>;
     fn repl_type(&self) -> String;
+    fn capabilities(&self) -> &Capabilities;
+
+    /// Waits up to `timeout` for a response that arrives outside of any
+    /// request, like output from a future, None when nothing arrived.
+    fn poll(&mut self, timeout: Duration) -> Result<Option<Response>>;
+
+    /// Sends the contents of a source file for evaluation, responses are
+    /// read with `recv` as for `send`.
+    fn send_file(&mut self, _path: &str, contents: &str) -> Result<()> {
+        self.send(&format!("(load-string {})", clojure_string(contents)))
+    }
+
+    /// Sends `s` for evaluation in namespace `ns`, leaving the namespace of
+    /// the session as it is.
+    fn send_in(&mut self, ns: &str, s: &str) -> Result<()> {
+        self.send(&format!(
+            "(binding [*ns* (the-ns '{})] (load-string {}))\n",
+            ns,
+            clojure_string(s)
+        ))
+    }
+
+    /// Runs the tests of `namespaces` with a test op of the server, None
+    /// when there is none and the tests need to be run with an eval.
+    fn test_op(&mut self, _namespaces: &[String]) -> Result<Option<TestReport>> {
+        Ok(None)
+    }
+}
+
+fn clojure_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn bencode_get<'a>(value: &'a bencode_rs::Value, key: &str) -> Option<&'a bencode_rs::Value> {
+    match value {
+        bencode_rs::Value::Map(map) => map.get(&bencode_rs::Value::Str(key.into())),
+        _ => None,
+    }
+}
+
+fn bencode_text(value: &bencode_rs::Value) -> String {
+    match value {
+        bencode_rs::Value::Str(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn has_status(value: &bencode_rs::Value, status: &str) -> bool {
+    match bencode_get(value, "status") {
+        Some(bencode_rs::Value::List(list)) => {
+            list.contains(&bencode_rs::Value::Str(status.into()))
+        }
+        _ => false,
+    }
+}
+
+trait Stream: Read + Write + Send {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<S: Stream> Stream for native_tls::TlsStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
+
+/// A connection to the REPL over TCP, a Unix domain socket or TLS. Clones
+/// share the stream so that reading and writing can be owned separately.
+#[derive(Clone)]
+struct Connection(Arc<Mutex<Box<dyn Stream>>>);
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+/// Waits up to `timeout` for something to read from `reader`. The end of
+/// the stream counts as something so that reading it reports the loss.
+fn has_input(reader: &mut BufReader<Connection>, timeout: Duration) -> Result<bool> {
+    reader
+        .get_ref()
+        .0
+        .lock()
+        .unwrap()
+        .set_read_timeout(Some(timeout))
+        .map_err(connection_lost)?;
+    let ready = match reader.fill_buf() {
+        Ok(_) => Ok(true),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+        Err(e) => Err(connection_lost(e)),
+    };
+    reader
+        .get_ref()
+        .0
+        .lock()
+        .unwrap()
+        .set_read_timeout(None)
+        .map_err(connection_lost)?;
+    ready
+}
+
+#[derive(Clone, Debug)]
+struct Tls {
+    ca: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+}
+
+/// Where the REPL is, `socket` takes precedence over `host` and `port`.
+#[derive(Clone, Debug)]
+struct Endpoint {
+    host: String,
+    port: usize,
+    socket: Option<PathBuf>,
+    tls: Option<Tls>,
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.socket {
+            Some(socket) => write!(f, "{}", socket.display())?,
+            None => write!(f, "{}:{}", self.host, self.port)?,
+        }
+        if self.tls.is_some() {
+            write!(f, " (TLS)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Endpoint {
+    fn connect(&self) -> Result<Connection> {
+        let stream: Box<dyn Stream> = match (&self.socket, &self.tls) {
+            #[cfg(unix)]
+            (Some(socket), None) => Box::new(UnixStream::connect(socket)?),
+            #[cfg(unix)]
+            (Some(socket), Some(tls)) => self.tls_connect(tls, UnixStream::connect(socket)?)?,
+            #[cfg(not(unix))]
+            (Some(_), _) => bail!("Unix domain sockets are not supported on this platform"),
+            (None, None) => Box::new(TcpStream::connect(format!("{}:{}", self.host, self.port))?),
+            (None, Some(tls)) => {
+                let stream = TcpStream::connect(format!("{}:{}", self.host, self.port))?;
+                self.tls_connect(tls, stream)?
+            }
+        };
+        Ok(Connection(Arc::new(Mutex::new(stream))))
+    }
+
+    fn tls_connect<S>(&self, tls: &Tls, stream: S) -> Result<Box<dyn Stream>>
+    where
+        S: Stream + fmt::Debug + 'static,
+    {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(ca) = &tls.ca {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(&fs::read(ca)?)?);
+        }
+        match (&tls.cert, &tls.key) {
+            (Some(cert), Some(key)) => {
+                let identity = native_tls::Identity::from_pkcs8(&fs::read(cert)?, &fs::read(key)?)?;
+                builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => bail!("Both --tls-cert and --tls-key are needed for a client certificate"),
+        }
+
+        // a socket has no host name to check the certificate against
+        let domain = if self.socket.is_some() {
+            "localhost"
+        } else {
+            self.host.as_str()
+        };
+        let stream = builder
+            .build()?
+            .connect(domain, stream)
+            .map_err(|e| anyhow!("TLS handshake with {} failed: {}", self, e))?;
+        Ok(Box::new(stream))
+    }
 }
 
 struct Nrepl {
     ns: String,
-    reader: BufReader<TcpStream>,
-    writer: TcpStream,
+    session: String,
+    capabilities: Capabilities,
+    reader: BufReader<Connection>,
+    writer: Connection,
+    // set by an 'eval-error' status, reported when the request is done
+    exception: Option<String>,
 }
 
 impl Nrepl {
-    fn new(stream: TcpStream) -> Result<Nrepl> {
-        let nrepl = Nrepl {
+    fn new(stream: Connection) -> Result<Nrepl> {
+        let mut nrepl = Nrepl {
             ns: "user".into(),
-            writer: stream.try_clone()?,
+            session: "".into(),
+            capabilities: Capabilities::default(),
+            writer: stream.clone(),
             reader: BufReader::new(stream),
+            exception: None,
         };
+        nrepl.capabilities = nrepl.describe()?;
+        if nrepl.capabilities.supports("clone") {
+            nrepl.session = nrepl.clone_session()?;
+        }
         Ok(nrepl)
     }
+
+    fn send_op(&mut self, fields: &[(&str, &str)]) -> Result<()> {
+        let map: HashMap<&str, &str> = fields.iter().cloned().collect();
+        write_and_flush(&mut self.writer, &bencode_rs::Value::from(map).to_bencode())
+            .map_err(connection_lost)
+    }
+
+    fn read_message(&mut self) -> Result<bencode_rs::Value> {
+        match bencode_rs::parse_bencode(&mut self.reader) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => bail!(ConnectionLost("nREPL died?".into())),
+            Err(e) => bail!(ConnectionLost(e.to_string())),
+        }
+    }
+
+    /// Reads the responses left to the request `msg` answered, up to 'done',
+    /// so that they don't end up read as responses to the next one.
+    fn drain(&mut self, mut msg: bencode_rs::Value) -> Result<()> {
+        while !has_status(&msg, "done") {
+            msg = self.read_message()?;
+        }
+        Ok(())
+    }
+
+    fn describe(&mut self) -> Result<Capabilities> {
+        let mut capabilities = Capabilities::default();
+        self.send_op(&[("op", "describe")])?;
+
+        loop {
+            let msg = self.read_message()?;
+            if let Some(bencode_rs::Value::Map(ops)) = bencode_get(&msg, "ops") {
+                let ops = ops
+                    .keys()
+                    .filter_map(|k| match k {
+                        bencode_rs::Value::Str(op) => Some(op.clone()),
+                        _ => None,
+                    })
+                    .collect();
+                capabilities.ops = Some(ops);
+            }
+            if let Some(bencode_rs::Value::Map(versions)) = bencode_get(&msg, "versions") {
+                for (k, v) in versions.iter() {
+                    if let (bencode_rs::Value::Str(name), Some(bencode_rs::Value::Str(version))) =
+                        (k, bencode_get(v, "version-string"))
+                    {
+                        capabilities.versions.insert(name.clone(), version.clone());
+                    }
+                }
+            }
+            if has_status(&msg, "done") {
+                break;
+            }
+        }
+
+        Ok(capabilities)
+    }
+
+    // Evals sent without a session get a throwaway session from nREPL, so the
+    // namespace would not stick between forms without this.
+    fn clone_session(&mut self) -> Result<String> {
+        self.send_op(&[("op", "clone")])?;
+
+        loop {
+            let msg = self.read_message()?;
+            if let Some(bencode_rs::Value::Str(s)) = bencode_get(&msg, "new-session") {
+                return Ok(s.into());
+            }
+            if has_status(&msg, "done") {
+                bail!("nREPL did not create a session");
+            }
+        }
+    }
 }
 
 impl Repl for Nrepl {
     fn quit(&mut self) -> Result<()> {
-        // write_and_flush(&mut self.writer, ":repl/quit\n")?;
+        // the session would live on in the server after the connection is gone
+        if !self.session.is_empty() {
+            let session = self.session.clone();
+            self.send_op(&[("op", "close"), ("session", &session)])?;
+        }
         Ok(())
     }
 
@@ -79,17 +496,110 @@ impl Repl for Nrepl {
         "nREPL".to_string()
     }
 
+    fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
     fn send(&mut self, s: &str) -> Result<()> {
         let mut map: HashMap<&str, &str> = HashMap::new();
 
         map.insert("op", "eval");
         map.insert("code", s);
+        if !self.session.is_empty() {
+            map.insert("session", &self.session);
+        }
 
-        write_and_flush(&mut self.writer, &bencode_rs::Value::from(map).to_bencode())?;
+        write_and_flush(&mut self.writer, &bencode_rs::Value::from(map).to_bencode())
+            .map_err(connection_lost)?;
 
         Ok(())
     }
 
+    fn send_in(&mut self, ns: &str, s: &str) -> Result<()> {
+        let session = self.session.clone();
+        let mut fields = vec![("op", "eval"), ("code", s), ("ns", ns)];
+        if !session.is_empty() {
+            fields.push(("session", session.as_str()));
+        }
+        self.send_op(&fields)
+    }
+
+    fn send_file(&mut self, path: &str, contents: &str) -> Result<()> {
+        if !self.capabilities.supports("load-file") {
+            return self.send(&format!("(load-string {})", clojure_string(contents)));
+        }
+
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let session = self.session.clone();
+        let mut fields = vec![
+            ("op", "load-file"),
+            ("file", contents),
+            ("file-path", path),
+            ("file-name", name.as_str()),
+        ];
+        if !session.is_empty() {
+            fields.push(("session", session.as_str()));
+        }
+        self.send_op(&fields)
+    }
+
+    // cider-nrepl's test op, responses carry a results map of
+    // ns -> var -> [result] and a summary of counts
+    fn test_op(&mut self, namespaces: &[String]) -> Result<Option<TestReport>> {
+        if !self.capabilities.supports("test") {
+            return Ok(None);
+        }
+
+        let mut report = TestReport::default();
+        for ns in namespaces {
+            let session = self.session.clone();
+            let mut fields = vec![("op", "test"), ("ns", ns.as_str())];
+            if !session.is_empty() {
+                fields.push(("session", session.as_str()));
+            }
+            self.send_op(&fields)?;
+
+            loop {
+                let msg = self.read_message()?;
+                if has_status(&msg, "unknown-op") {
+                    self.drain(msg)?;
+                    return Ok(None);
+                }
+                if let Some(bencode_rs::Value::Map(results)) = bencode_get(&msg, "results") {
+                    for (_, vars) in results.iter() {
+                        if let bencode_rs::Value::Map(vars) = vars {
+                            for (var, results) in vars.iter() {
+                                if let bencode_rs::Value::List(results) = results {
+                                    report.add_cider_results(&bencode_text(var), results);
+                                }
+                            }
+                        }
+                    }
+                }
+                if has_status(&msg, "namespace-not-found") {
+                    self.drain(msg)?;
+                    bail!("Namespace {} not found", ns);
+                }
+                if has_status(&msg, "done") {
+                    break;
+                }
+            }
+        }
+
+        Ok(Some(report))
+    }
+
+    fn poll(&mut self, timeout: Duration) -> Result<Option<Response>> {
+        if has_input(&mut self.reader, timeout)? {
+            self.recv().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     fn recv(&mut self) -> Result<Response> {
         match bencode_rs::parse_bencode(&mut self.reader) {
             Ok(Some(bencode_rs::Value::Map(map))) => {
@@ -109,12 +619,25 @@ impl Repl for Nrepl {
                 } else if let Some(bencode_rs::Value::Str(s)) =
                     map.get(&bencode_rs::Value::Str("value".into()))
                 {
-                    return Ok(Response::StdOut(format!("{}\n", s)));
+                    return Ok(Response::Value(s.into()));
                 } else if let Some(bencode_rs::Value::List(list)) =
                     map.get(&bencode_rs::Value::Str("status".into()))
                 {
-                    if list.contains(&bencode_rs::Value::Str("done".into())) {
-                        return Ok(Response::Done(None));
+                    if list.contains(&bencode_rs::Value::Str("eval-error".into())) {
+                        self.exception = map
+                            .get(&bencode_rs::Value::Str("ex".into()))
+                            .map(bencode_text)
+                            .or_else(|| Some("Evaluation failed".into()));
+                    }
+                    if list.contains(&bencode_rs::Value::Str("unknown-op".into())) {
+                        return Ok(Response::Exception(
+                            "Operation not supported by nREPL".into(),
+                        ));
+                    } else if list.contains(&bencode_rs::Value::Str("done".into())) {
+                        return Ok(match self.exception.take() {
+                            Some(ex) => Response::Exception(ex),
+                            None => Response::Done(None),
+                        });
                     } else {
                         return Ok(Response::Other("".into()));
                     }
@@ -122,9 +645,9 @@ impl Repl for Nrepl {
                     bail!("Malformat response from nREPL: {:?}", map);
                 }
             }
-            Ok(None) => bail!("nREPL died?"),
+            Ok(None) => bail!(ConnectionLost("nREPL died?".into())),
             Ok(_) => bail!("Unexpected response from nREPL"),
-            Err(e) => bail!("Error: {} ", e),
+            Err(e) => bail!(ConnectionLost(e.to_string())),
         }
     }
 }
@@ -137,26 +660,91 @@ fn is_valid_form(s: &str) -> bool {
     }
 }
 
+fn is_sendable(repl: &dyn Repl, code: &str) -> bool {
+    if repl.repl_type() != "pREPL" {
+        return true;
+    }
+
+    use std::panic;
+
+    let result = panic::catch_unwind(|| {
+        // This ugly pacic catching is needed for prepl, which is stream based and
+        // expects correctly formatted forms in one go. So before sending forms to
+        // prepl they need to be validated and the edn library used for validating
+        // occationally panics for invalid cases..
+        if !is_valid_form(code) {
+            panic!();
+        }
+    });
+
+    result.is_ok()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PreplTag {
+    Ret,
+    Out,
+    Err,
+    Tap,
+}
+
+#[derive(Deserialize)]
+struct PreplResponse {
+    tag: PreplTag,
+    val: String,
+    ns: Option<String>,
+    exception: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct PreplException {
+    cause: Option<String>,
+}
+
 struct Prepl {
     ns: String,
-    reader: BufReader<TcpStream>,
-    writer: TcpStream,
+    capabilities: Capabilities,
+    reader: BufReader<Connection>,
+    writer: Connection,
 }
 
 impl Prepl {
-    fn new(stream: TcpStream) -> Result<Prepl> {
-        let prepl = Prepl {
+    fn new(stream: Connection) -> Result<Prepl> {
+        let mut prepl = Prepl {
             ns: "user".into(),
-            writer: stream.try_clone()?,
+            capabilities: Capabilities::default(),
+            writer: stream.clone(),
             reader: BufReader::new(stream),
         };
+        prepl.capabilities = prepl.describe()?;
         Ok(prepl)
     }
+
+    // pREPL has no ops besides eval, so versions are asked with a form instead.
+    fn describe(&mut self) -> Result<Capabilities> {
+        let mut capabilities = Capabilities {
+            ops: Some(["eval".to_string()].iter().cloned().collect()),
+            versions: BTreeMap::new(),
+        };
+        let mut sink = std::io::sink();
+
+        let form = "[(clojure-version) (System/getProperty \"java.version\")]\n";
+        if let Some(val) = eval(self, form, &mut sink, &mut std::io::sink())? {
+            if let Ok(versions) = serde_edn::from_str::<Vec<String>>(&val) {
+                for (name, version) in ["clojure", "java"].iter().zip(versions) {
+                    capabilities.versions.insert(name.to_string(), version);
+                }
+            }
+        }
+
+        Ok(capabilities)
+    }
 }
 
 impl Repl for Prepl {
     fn quit(&mut self) -> Result<()> {
-        write_and_flush(&mut self.writer, ":repl/quit\n")?;
+        write_and_flush(&mut self.writer, ":repl/quit\n").map_err(connection_lost)?;
         Ok(())
     }
 
@@ -168,63 +756,54 @@ impl Repl for Prepl {
         "pREPL".to_string()
     }
 
+    fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
     fn send(&mut self, s: &str) -> Result<()> {
-        write_and_flush(&mut self.writer, &s)?;
+        write_and_flush(&mut self.writer, &s).map_err(connection_lost)?;
         Ok(())
     }
 
+    fn poll(&mut self, timeout: Duration) -> Result<Option<Response>> {
+        if has_input(&mut self.reader, timeout)? {
+            self.recv().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     fn recv(&mut self) -> Result<Response> {
         let mut buf = String::from("");
-        self.reader.read_line(&mut buf)?;
-        let mut parser = Parser::new(&buf);
-        let response = parser
-            .read()
-            .ok_or(format_err!("Unexpected 'None'-response from pREPL"))?;
+        if self.reader.read_line(&mut buf).map_err(connection_lost)? == 0 {
+            bail!(ConnectionLost("pREPL closed the connection".into()));
+        }
+        let response: PreplResponse = serde_edn::from_str(&buf)
+            .map_err(|e| anyhow!("Unexpected pREPL-response '{}': {}", buf.trim(), e))?;
+        if let Some(ns) = &response.ns {
+            self.ns = ns.clone();
+        }
 
-        match response {
-            Ok(edn::Value::Map(map)) => {
-                let val = get_value("val", &map).ok_or(anyhow!("'val' not found in response"))?;
-                let tag = get_value("tag", &map).ok_or(anyhow!("'tag' not found in response"))?;
-                self.ns = get_value("ns", &map).unwrap_or(self.get_ns());
-                match tag.as_str() {
-                    "err" => {
-                        return Ok(Response::StdErr(val.into()));
-                    }
-                    "out" => {
-                        return Ok(Response::StdOut(val.into()));
-                    }
-                    "ret" => {
-                        if get_value("exception", &map).is_some() {
-                            let mut parser = Parser::new(val.as_str());
-                            if let Ok(edn::Value::Map(emap)) = parser
-                                .read()
-                                .ok_or(anyhow!("Unable to parse exception '{}'", val))?
-                            {
-                                match get_value("cause", &emap) {
-                                    Some(cause) => return Ok(Response::Exception(cause)),
-                                    None => bail!("Unable to parse error '{}'", val),
-                                }
-                            } else {
-                                bail!("Unable to parse error '{}'", val);
-                            }
-                        } else {
-                            return Ok(Response::Done(Some(val)));
-                        }
-                    }
-                    _ => bail!("Unknown tag in response '{:?}'", tag),
+        match response.tag {
+            PreplTag::Err => Ok(Response::StdErr(response.val)),
+            PreplTag::Out => Ok(Response::StdOut(response.val)),
+            PreplTag::Tap => Ok(Response::Tap(response.val)),
+            PreplTag::Ret if response.exception == Some(true) => {
+                let exception: PreplException = serde_edn::from_str(&response.val)
+                    .map_err(|_| anyhow!("Unable to parse error '{}'", response.val))?;
+                match exception.cause {
+                    Some(cause) => Ok(Response::Exception(cause)),
+                    None => bail!("Unable to parse error '{}'", response.val),
                 }
             }
-            Ok(_) => bail!("Unexpected pREPL-response '{:?}'", response),
-            Err(e) => bail!("Parse error: {}", e.message),
+            PreplTag::Ret => Ok(Response::Done(Some(response.val))),
         }
     }
 }
 
-fn readline(namespace: &str) -> Result<Option<String>> {
-    let mut rl = Editor::<()>::new();
-
+fn readline(rl: &mut Editor<ReplHelper>, prompt: &str) -> Result<Option<String>> {
     loop {
-        let readline = rl.readline(&format!("{}=> ", &namespace));
+        let readline = rl.readline(&format!("{}=> ", &prompt));
         match readline {
             Ok(line) => {
                 let line = line.trim();
@@ -250,74 +829,2738 @@ fn readline(namespace: &str) -> Result<Option<String>> {
     }
 }
 
-fn main_loop(mut repl: Box<dyn Repl>) -> Result<()> {
-    let mut out = stdout();
-    let mut err = stderr();
+/// ANSI color codes for the parts of the output that can be colored.
+#[derive(Clone, Default)]
+struct Colors {
+    prompt: Option<String>,
+    value: Option<String>,
+    err: Option<String>,
+    exception: Option<String>,
+}
 
-    loop {
-        match readline(&repl.get_ns())? {
-            Some(s) => {
-                if repl.repl_type() == "pREPL" {
-                    use std::panic;
-
-                    let result = panic::catch_unwind(|| {
-                        // This ugly pacic catching is needed for prepl, which is stream based and
-                        // expects correctly formatted forms in one go. So before sending forms to
-                        // prepl they need to be validated and the edn library used for validating
-                        // occationally panics for invalid cases..
-                        if !is_valid_form(&s) {
-                            panic!();
+fn color_code(name: &str) -> Option<String> {
+    let code = match name {
+        "black" => 30,
+        "red" => 31,
+        "green" => 32,
+        "yellow" => 33,
+        "blue" => 34,
+        "magenta" => 35,
+        "cyan" => 36,
+        "white" => 37,
+        "gray" | "grey" => 90,
+        _ => return None,
+    };
+    Some(code.to_string())
+}
+
+fn paint(text: &str, color: Option<&str>) -> String {
+    match color {
+        Some(code) if !text.is_empty() => format!("\x1b[{}m{}\x1b[0m", code, text),
+        _ => text.into(),
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+struct Profile {
+    host: Option<String>,
+    port: Option<usize>,
+    init: Vec<String>,
+}
+
+/// Delimiters and string literals of a line, as byte offsets.
+struct Scan {
+    // both ends of each balanced pair map to the other end
+    matches: HashMap<usize, usize>,
+    // offsets of the opening and closing quote, the length of the line when
+    // the string is unterminated
+    strings: Vec<(usize, usize)>,
+}
+
+fn closer(open: u8) -> u8 {
+    match open {
+        b'(' => b')',
+        b'[' => b']',
+        _ => b'}',
+    }
+}
+
+fn is_delimiter(c: u8) -> bool {
+    b"()[]{}\"".contains(&c)
+}
+
+fn is_whitespace(c: u8) -> bool {
+    c.is_ascii_whitespace() || c == b','
+}
+
+impl Scan {
+    fn new(line: &str) -> Scan {
+        let bytes = line.as_bytes();
+        let mut scan = Scan {
+            matches: HashMap::new(),
+            strings: vec![],
+        };
+        let mut stack = vec![];
+        let mut i = 0;
+
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 1,
+                b';' => {
+                    while i < bytes.len() && bytes[i] != b'\n' {
+                        i += 1;
+                    }
+                }
+                b'"' => {
+                    let start = i;
+                    i += 1;
+                    while i < bytes.len() && bytes[i] != b'"' {
+                        if bytes[i] == b'\\' {
+                            i += 1;
                         }
-                    });
-                    if result.is_err() {
-                        println!("Not a valid form '{}'", s);
-                        continue;
+                        i += 1;
                     }
+                    scan.strings.push((start, min(i, bytes.len())));
                 }
-                repl.send(&s)?;
-            }
-            None => {
-                repl.quit()?;
-                break;
+                b'(' | b'[' | b'{' => stack.push(i),
+                c if b")]}".contains(&c) => {
+                    if let Some(&open) = stack.last() {
+                        if closer(bytes[open]) == c {
+                            stack.pop();
+                            scan.matches.insert(open, i);
+                            scan.matches.insert(i, open);
+                        }
+                    }
+                }
+                _ => {}
             }
+            i += 1;
         }
-        loop {
-            match repl.recv()? {
-                Response::StdErr(s) => {
-                    write_and_flush(&mut err, &s)?;
+
+        scan
+    }
+
+    /// The string literal `pos` is inside of, i.e. after its opening quote.
+    fn string_at(&self, pos: usize) -> Option<(usize, usize)> {
+        self.strings
+            .iter()
+            .cloned()
+            .find(|(start, end)| *start < pos && pos <= *end)
+    }
+
+    /// The innermost balanced pair around `pos`.
+    fn enclosing(&self, line: &str, pos: usize) -> Option<(usize, usize)> {
+        self.matches
+            .iter()
+            .map(|(a, b)| (*a, *b))
+            .filter(|(open, close)| {
+                open < close
+                    && *open < pos
+                    && pos <= *close
+                    && b"([{".contains(&line.as_bytes()[*open])
+            })
+            .max()
+    }
+
+    /// End of the form starting at `start`, None when it is not complete.
+    fn form_end(&self, line: &str, start: usize) -> Option<usize> {
+        let bytes = line.as_bytes();
+        match *bytes.get(start)? {
+            b'(' | b'[' | b'{' => self.matches.get(&start).map(|close| close + 1),
+            b')' | b']' | b'}' | b';' => None,
+            b'"' => self
+                .strings
+                .iter()
+                .find(|(s, _)| *s == start)
+                .filter(|(_, end)| *end < bytes.len())
+                .map(|(_, end)| end + 1),
+            b'\'' | b'`' | b'~' | b'@' | b'^' => self.form_end(line, start + 1),
+            b'#' if bytes.get(start + 1).is_some_and(|c| b"({\"_'?".contains(c)) => {
+                let mut next = start + 1;
+                while next < bytes.len()
+                    && (b"_'?@".contains(&bytes[next]) || is_whitespace(bytes[next]))
+                {
+                    next += 1;
                 }
-                Response::StdOut(s) => {
-                    write_and_flush(&mut out, &s)?;
+                self.form_end(line, next)
+            }
+            _ => {
+                let mut end = start;
+                while end < bytes.len() && !is_whitespace(bytes[end]) {
+                    if bytes[end] == b'\\' {
+                        end += 1;
+                    } else if end > start && is_delimiter(bytes[end]) {
+                        break;
+                    }
+                    end += 1;
                 }
-                Response::Exception(s) => {
-                    write_and_flush(&mut out, &format!("{}\n", &s))?;
-                    break;
+                Some(min(end, bytes.len())).filter(|end| *end > start)
+            }
+        }
+    }
+
+    /// Complete forms between `from` and `to`.
+    fn forms(&self, line: &str, from: usize, to: usize) -> Vec<(usize, usize)> {
+        let bytes = line.as_bytes();
+        let mut forms = vec![];
+        let mut i = from;
+
+        while i < to {
+            if is_whitespace(bytes[i]) {
+                i += 1;
+            } else if bytes[i] == b';' {
+                while i < to && bytes[i] != b'\n' {
+                    i += 1;
                 }
-                Response::Other(_) => {}
-                Response::Done(opt) => {
-                    if let Some(s) = opt {
-                        write_and_flush(&mut out, &format!("{}\n", &s))?;
+            } else {
+                match self.form_end(line, i) {
+                    Some(end) if end <= to => {
+                        forms.push((i, end));
+                        i = end;
                     }
-                    break;
+                    _ => break,
                 }
             }
         }
+
+        forms
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Paredit {
+    Open(u8),
+    Close(u8),
+    Quote,
+    Backspace,
+    Delete,
+    KillForms,
+    KillForm,
+    Slurp,
+    Barf,
+}
+
+fn splice(line: &str, from: usize, to: usize, text: &str) -> String {
+    format!("{}{}{}", &line[..from], text, &line[to..])
+}
+
+/// The line and cursor position after a structural edit, None when the key
+/// should do what it normally does.
+fn paredit(edit: Paredit, line: &str, pos: usize) -> Option<(String, usize)> {
+    let scan = Scan::new(line);
+    let bytes = line.as_bytes();
+    let prev = if pos > 0 {
+        bytes.get(pos - 1).cloned()
+    } else {
+        None
+    };
+    let next = bytes.get(pos).cloned();
+    let string = scan.string_at(pos);
+
+    match edit {
+        Paredit::Open(open) if string.is_none() && prev != Some(b'\\') => {
+            let pair = format!("{}{}", open as char, closer(open) as char);
+            Some((splice(line, pos, pos, &pair), pos + 1))
+        }
+        Paredit::Close(close) if string.is_none() && next == Some(close) => {
+            Some((line.to_string(), pos + 1))
+        }
+        Paredit::Quote => match string {
+            Some((_, end)) if end == line.len() => None,
+            Some((_, end)) if end == pos && next == Some(b'"') => Some((line.to_string(), pos + 1)),
+            _ if prev == Some(b'\\') => None,
+            Some(_) => Some((splice(line, pos, pos, "\\\""), pos + 2)),
+            None => Some((splice(line, pos, pos, "\"\""), pos + 1)),
+        },
+        Paredit::Backspace => {
+            let prev_pos = pos.checked_sub(1)?;
+            let prev = bytes[prev_pos];
+            // An empty pair goes away one side at a time, the closing one
+            // first, as no command removes text on both sides of the cursor.
+            if let Some((start, end)) = string {
+                let closed = end < line.len();
+                return match (start == prev_pos, end == pos) {
+                    (true, true) if closed => Some((splice(line, pos, pos + 1, ""), pos)),
+                    (true, false) if closed => Some((line.to_string(), prev_pos)),
+                    _ => None,
+                };
+            }
+            if let Some(&other) = scan.matches.get(&prev_pos) {
+                return if other == pos {
+                    Some((splice(line, pos, pos + 1, ""), pos))
+                } else {
+                    Some((line.to_string(), prev_pos))
+                };
+            }
+            if prev == b'"' && scan.string_at(prev_pos).is_some() {
+                return Some((line.to_string(), prev_pos));
+            }
+            None
+        }
+        Paredit::Delete => {
+            let next = next?;
+            if let Some((_, end)) = string {
+                return Some((line.to_string(), pos + 1)).filter(|_| end == pos);
+            }
+            if let Some(&other) = scan.matches.get(&pos) {
+                return if other == pos + 1 {
+                    Some((splice(line, pos, pos + 2, ""), pos))
+                } else {
+                    Some((line.to_string(), pos + 1))
+                };
+            }
+            if next == b'"' {
+                return match scan.strings.iter().find(|(start, _)| *start == pos) {
+                    Some((_, end)) if *end == line.len() => None,
+                    Some((_, end)) if *end == pos + 1 => {
+                        Some((splice(line, pos, pos + 2, ""), pos))
+                    }
+                    _ => Some((line.to_string(), pos + 1)),
+                };
+            }
+            None
+        }
+        Paredit::KillForms => {
+            let end = match (string, scan.enclosing(line, pos)) {
+                (Some((_, end)), _) => end,
+                (None, Some((_, close))) => close,
+                (None, None) => line.len(),
+            };
+            if string.is_some() {
+                return Some((splice(line, pos, end, ""), pos));
+            }
+            let forms = scan.forms(line, pos, end);
+            let last = forms.last().map(|(_, form_end)| *form_end).unwrap_or(pos);
+            Some((splice(line, pos, last, ""), pos))
+        }
+        Paredit::KillForm => {
+            if string.is_some() {
+                return None;
+            }
+            let end = scan
+                .enclosing(line, pos)
+                .map(|(_, close)| close)
+                .unwrap_or_else(|| line.len());
+            let (_, form_end) = *scan.forms(line, pos, end).first()?;
+            Some((splice(line, pos, form_end, ""), pos))
+        }
+        Paredit::Slurp => {
+            let (_, close) = scan.enclosing(line, pos)?;
+            let outer = scan
+                .enclosing(line, close + 1)
+                .map(|(_, outer)| outer)
+                .unwrap_or_else(|| line.len());
+            let (_, form_end) = *scan.forms(line, close + 1, outer).first()?;
+            let slurped = format!("{}{}", &line[close + 1..form_end], bytes[close] as char);
+            Some((splice(line, close, form_end, &slurped), pos))
+        }
+        Paredit::Barf => {
+            let (open, close) = scan.enclosing(line, pos)?;
+            let (start, _) = *scan.forms(line, open + 1, close).last()?;
+            let kept = line[..start].trim_end().len();
+            let barfed = format!("{} {}", bytes[close] as char, &line[start..close]);
+            let pos = if pos <= kept {
+                pos
+            } else if pos < start {
+                kept
+            } else {
+                pos - (start - kept) + 2
+            };
+            Some((splice(line, kept, close + 1, &barfed), pos))
+        }
+        _ => None,
+    }
+}
+
+/// The rustyline command that turns `line` into `new_line` and moves the
+/// cursor from `pos` to `new_pos`. None when there is no such command, as
+/// commands can't insert on both sides of the cursor or leave it inside the
+/// text they insert. Only in vi mode `Cmd::Insert` leaves it on the last
+/// character inserted.
+fn command(line: &str, pos: usize, new_line: &str, new_pos: usize, emacs: bool) -> Option<Cmd> {
+    let chars = |s: &str| s.chars().count();
+    let (before, after) = line.split_at(pos);
+
+    if new_line == line {
+        return Some(if new_pos >= pos {
+            Cmd::Move(Movement::ForwardChar(chars(&line[pos..new_pos])))
+        } else {
+            Cmd::Move(Movement::BackwardChar(chars(&line[new_pos..pos])))
+        });
+    }
+    if new_line.len() > line.len() && new_line.starts_with(before) && new_line.ends_with(after) {
+        let text = &new_line[pos..new_line.len() - after.len()];
+        let last = text.chars().last().map_or(0, char::len_utf8);
+        if new_pos == pos + text.len() - if emacs { 0 } else { last } {
+            return Some(Cmd::Insert(1, text.into()));
+        }
+    }
+    if new_pos > pos || new_line.get(..new_pos) != Some(&line[..new_pos]) {
+        return None;
+    }
+    if new_line.ends_with(after) && new_line.len() - after.len() >= new_pos {
+        let killed = Movement::BackwardChar(chars(&line[new_pos..pos]));
+        return Some(match &new_line[new_pos..new_line.len() - after.len()] {
+            "" => Cmd::Kill(killed),
+            text => Cmd::Replace(killed, Some(text.into())),
+        });
+    }
+    if new_pos < pos {
+        return None;
+    }
+    match after.strip_suffix(&new_line[pos..]) {
+        Some(killed) => Some(Cmd::Kill(Movement::ForwardChar(chars(killed)))),
+        None => Some(Cmd::Replace(
+            Movement::EndOfBuffer,
+            Some(new_line[pos..].into()),
+        )),
+    }
+}
+
+struct ParenHandler(Paredit);
+
+impl ConditionalEventHandler for ParenHandler {
+    fn handle(
+        &self,
+        _: &rustyline::Event,
+        _: RepeatCount,
+        _: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        let emacs = ctx.mode() == EditMode::Emacs;
+        if !emacs && ctx.input_mode() != InputMode::Insert {
+            return None;
+        }
+        let (line, pos) = paredit(self.0, ctx.line(), ctx.pos())?;
+        // an edit no command can make leaves the line alone, what the key
+        // usually does would unbalance it
+        Some(command(ctx.line(), ctx.pos(), &line, pos, emacs).unwrap_or(Cmd::Noop))
+    }
+}
+
+#[derive(Helper, Hinter, Validator)]
+struct ReplHelper {
+    brackets: Option<MatchingBracketHighlighter>,
+    namespaces: Arc<Mutex<Vec<String>>>,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "*+!-_?<>=./".contains(c)
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_symbol_char(*c))
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(pos);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, vec![]));
+        }
+        let candidates = self
+            .namespaces
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|ns| ns.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        match &self.brackets {
+            Some(brackets) => brackets.highlight(line, pos),
+            None => Cow::Borrowed(line),
+        }
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize) -> bool {
+        match &self.brackets {
+            Some(brackets) => brackets.highlight_char(line, pos),
+            None => false,
+        }
+    }
+}
+
+/// Binds the structural editing keys. rustyline has no emacs command that
+/// leaves the cursor between the delimiters it inserts, so in emacs mode
+/// delimiters are typed and deleted as usual.
+fn bind_paredit(rl: &mut Editor<ReplHelper>, vi: bool) {
+    let mut keys = vec![
+        (KeyEvent::ctrl('K'), Paredit::KillForms),
+        (KeyEvent::alt('k'), Paredit::KillForm),
+        (KeyEvent(KeyCode::Right, Modifiers::CTRL), Paredit::Slurp),
+        (KeyEvent(KeyCode::Left, Modifiers::CTRL), Paredit::Barf),
+    ];
+    let typing = vec![
+        (KeyEvent::from('('), Paredit::Open(b'(')),
+        (KeyEvent::from('['), Paredit::Open(b'[')),
+        (KeyEvent::from('{'), Paredit::Open(b'{')),
+        (KeyEvent::from(')'), Paredit::Close(b')')),
+        (KeyEvent::from(']'), Paredit::Close(b']')),
+        (KeyEvent::from('}'), Paredit::Close(b'}')),
+        (KeyEvent::from('"'), Paredit::Quote),
+        (
+            KeyEvent(KeyCode::Backspace, Modifiers::NONE),
+            Paredit::Backspace,
+        ),
+        (KeyEvent(KeyCode::Delete, Modifiers::NONE), Paredit::Delete),
+    ];
+    if vi {
+        keys.extend(typing);
+    }
+    for (key, edit) in keys {
+        rl.bind_sequence(key, EventHandler::Conditional(Box::new(ParenHandler(edit))));
+    }
+}
+
+/// Settings from `~/.config/rclj/config.edn` overridden by `.rclj.edn` in the
+/// current directory, e.g.
+///
+/// ```clojure
+/// {:profiles {:local {:host "127.0.0.1" :port 7888 :init ["(require 'dev)"]}}
+///  :default-profile :local
+///  :init ["(require '[clojure.repl :refer [doc source]])"]
+///  :colors {:prompt :green :value :cyan :err :red :exception :red}
+///  :edit-mode :vi
+///  :history-size 500
+///  :paredit false
+///  :commands {:doc "(clojure.repl/doc %1)" :req "(require '%1 :reload)"}}
+/// ```
+///
+/// In `:commands` `%1`..`%9` are replaced with the arguments of the command
+/// and `%&` with all of them. `:paredit false` turns off the structural
+/// editing keys of the input line: Ctrl-K/Alt-K to kill forms, Ctrl-Right/
+/// Ctrl-Left to slurp and barf and, in vi mode only, delimiters closed as
+/// they are typed and kept balanced on Backspace and Delete.
+#[derive(Default)]
+struct Config {
+    profiles: BTreeMap<String, Profile>,
+    default_profile: Option<String>,
+    init: Vec<String>,
+    colors: Colors,
+    vi_mode: bool,
+    history_size: Option<usize>,
+    commands: BTreeMap<String, String>,
+    paredit: bool,
+}
+
+/// A config file as written, merged over the files read before it.
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct ConfigFile {
+    profiles: BTreeMap<String, Profile>,
+    default_profile: Option<String>,
+    init: Vec<String>,
+    colors: Option<ColorNames>,
+    edit_mode: Option<String>,
+    history_size: Option<usize>,
+    paredit: Option<bool>,
+    commands: BTreeMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ColorNames {
+    prompt: Option<String>,
+    value: Option<String>,
+    err: Option<String>,
+    exception: Option<String>,
+}
+
+impl Config {
+    fn load() -> Result<Config> {
+        let mut config = Config {
+            paredit: true,
+            ..Config::default()
+        };
+        let mut paths = vec![];
+        if let Some(home) = std::env::var_os("HOME") {
+            paths.push(PathBuf::from(home).join(".config/rclj/config.edn"));
+        }
+        paths.push(PathBuf::from(".rclj.edn"));
+
+        for path in paths.iter().filter(|path| path.exists()) {
+            let file = serde_edn::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| anyhow!("Invalid config {}: {}", path.display(), e))?;
+            config.merge(file);
+        }
+
+        Ok(config)
+    }
+
+    fn merge(&mut self, file: ConfigFile) {
+        self.profiles.extend(file.profiles);
+        if file.default_profile.is_some() {
+            self.default_profile = file.default_profile;
+        }
+        self.init.extend(file.init);
+        if let Some(colors) = file.colors {
+            let color = |name: Option<String>| name.as_deref().and_then(color_code);
+            self.colors = Colors {
+                prompt: color(colors.prompt).or_else(|| self.colors.prompt.clone()),
+                value: color(colors.value).or_else(|| self.colors.value.clone()),
+                err: color(colors.err).or_else(|| self.colors.err.clone()),
+                exception: color(colors.exception).or_else(|| self.colors.exception.clone()),
+            };
+        }
+        if let Some(mode) = file.edit_mode {
+            self.vi_mode = mode == "vi";
+        }
+        if file.history_size.is_some() {
+            self.history_size = file.history_size;
+        }
+        if let Some(paredit) = file.paredit {
+            self.paredit = paredit;
+        }
+        self.commands.extend(file.commands);
+    }
+
+    fn editor(&self) -> Editor<ReplHelper> {
+        let mut builder = rustyline::Config::builder().completion_type(CompletionType::List);
+        if self.vi_mode {
+            builder = builder.edit_mode(EditMode::Vi);
+        }
+        if let Some(size) = self.history_size {
+            builder = builder.max_history_size(size);
+        }
+        let mut rl = Editor::with_config(builder.build());
+        if self.paredit {
+            bind_paredit(&mut rl, self.vi_mode);
+        }
+        rl.set_helper(Some(ReplHelper {
+            namespaces: Arc::default(),
+            brackets: if self.paredit {
+                Some(MatchingBracketHighlighter::new())
+            } else {
+                None
+            },
+        }));
+        rl
+    }
+
+    /// Expands `:name args..` when `name` is one of the user defined commands.
+    fn expand_command(&self, line: &str) -> Option<String> {
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let snippet = self.commands.get(parts.next()?.strip_prefix(':')?)?;
+        let args = split_forms(parts.next().unwrap_or(""));
+
+        let mut code = snippet.replace("%&", &args.join(" "));
+        for i in (1..=9).rev() {
+            let arg = args.get(i - 1).map(|s| s.as_str()).unwrap_or("");
+            code = code.replace(&format!("%{}", i), arg);
+        }
+        Some(code)
+    }
+}
+
+/// Evaluates the configured init forms, reporting the ones that fail.
+fn run_init_forms(repl: &mut dyn Repl, forms: &[String]) -> Result<()> {
+    for form in forms {
+        if let Err(e) = eval_quiet(repl, form) {
+            if is_connection_lost(&e) {
+                return Err(e);
+            }
+            eprintln!("Init form '{}' failed: {}", form, e);
+        }
+    }
+    Ok(())
+}
+
+fn print_capabilities(repl: &dyn Repl) {
+    let capabilities = repl.capabilities();
+
+    println!("{}", repl.repl_type());
+    for (name, version) in capabilities.versions.iter() {
+        println!("  {:<16}{}", name, version);
+    }
+    match &capabilities.ops {
+        Some(ops) => {
+            let ops: Vec<&str> = ops.iter().map(|op| op.as_str()).collect();
+            println!("  {:<16}{}", "ops", ops.join(" "));
+        }
+        None => println!("  {:<16}unknown", "ops"),
+    }
+}
+
+/// Splits source text into top-level forms. Comments and forms discarded
+/// with `#_` are dropped, metadata and tags stay attached to the form they
+/// annotate.
+fn split_forms(src: &str) -> Vec<String> {
+    fn end_element(current: &mut String, elem_start: &mut usize, forms: &mut Vec<String>) {
+        let elem = &current[*elem_start..];
+        let tag = elem.starts_with('#') && elem[1..].starts_with(char::is_alphabetic);
+        if elem.starts_with('^') || elem == "#_" || tag {
+            current.push(' ');
+            *elem_start = current.len();
+            return;
+        }
+        let form = current.trim();
+        if !form.is_empty() && !form.starts_with("#_") {
+            forms.push(form.to_string());
+        }
+        current.clear();
+        *elem_start = 0;
+    }
+
+    let mut forms = vec![];
+    let mut current = String::new();
+    let mut elem_start = 0;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut chars = src.chars();
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            current.push(c);
+            if c == '\\' {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            } else if c == '"' {
+                in_string = false;
+                if depth == 0 {
+                    end_element(&mut current, &mut elem_start, &mut forms);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            ';' => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                if depth > 0 {
+                    current.push('\n');
+                } else if current.len() > elem_start {
+                    end_element(&mut current, &mut elem_start, &mut forms);
+                }
+            }
+            '"' => {
+                current.push(c);
+                in_string = true;
+            }
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            '(' | '[' | '{' => {
+                current.push(c);
+                depth += 1;
+            }
+            ')' | ']' | '}' => {
+                current.push(c);
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    end_element(&mut current, &mut elem_start, &mut forms);
+                }
+            }
+            c if c.is_whitespace() || c == ',' => {
+                if depth > 0 {
+                    current.push(c);
+                } else if current.len() > elem_start {
+                    end_element(&mut current, &mut elem_start, &mut forms);
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    end_element(&mut current, &mut elem_start, &mut forms);
+
+    forms
+}
+
+/// How many delimiters are left open at the end of `src`, strings and
+/// comments excluded.
+fn delimiter_depth(src: &str) -> isize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut chars = src.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            ';' if !in_string => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' | '[' | '{' if !in_string => depth += 1,
+            ')' | ']' | '}' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth
+}
+
+/// Log of every form sent and every response received, written only when a
+/// transcript file was given.
+struct Transcript {
+    file: Option<File>,
+}
+
+impl Transcript {
+    fn open(path: Option<&Path>) -> Result<Transcript> {
+        let file = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        Ok(Transcript { file })
+    }
+
+    fn log(&mut self, kind: &str, text: &str) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+            writeln!(file, "{} {:<9} {:?}", now, kind, text)?;
+        }
+        Ok(())
+    }
+
+    fn input(&mut self, form: &str) -> Result<()> {
+        self.log("in", form)
+    }
+
+    fn response(&mut self, response: &Response) -> Result<()> {
+        for (kind, val) in response_events(response) {
+            self.log(kind, val.unwrap_or(""))?;
+        }
+        Ok(())
+    }
+}
+
+fn save_forms(path: &str, forms: &[String]) -> Result<()> {
+    let mut file = File::create(path)?;
+    for form in forms {
+        writeln!(file, "{}", form)?;
+    }
+    Ok(())
+}
+
+fn print_response(response: &Response, out: &mut dyn Write, err: &mut dyn Write) -> Result<()> {
+    match response {
+        Response::StdErr(s) => write_and_flush(err, s),
+        Response::StdOut(s) => write_and_flush(out, s),
+        Response::Tap(s) => write_and_flush(out, &format!("tap> {}\n", s)),
+        Response::Value(s) | Response::Exception(s) | Response::Done(Some(s)) => {
+            write_and_flush(out, &format!("{}\n", s))
+        }
+        Response::Done(None) | Response::Other(_) => Ok(()),
+    }
+}
+
+/// Evaluates `s` passing every response and the current namespace to
+/// `handler`. Returns the value produced, or `None` when the evaluation ended
+/// in an exception.
+fn eval_with(
+    repl: &mut dyn Repl,
+    s: &str,
+    handler: &mut dyn FnMut(&Response, &str) -> Result<()>,
+) -> Result<Option<String>> {
+    eval_in_with(repl, None, s, handler)
+}
+
+/// Like `eval_with`, in namespace `ns` when given.
+fn eval_in_with(
+    repl: &mut dyn Repl,
+    ns: Option<&str>,
+    s: &str,
+    handler: &mut dyn FnMut(&Response, &str) -> Result<()>,
+) -> Result<Option<String>> {
+    match ns {
+        Some(ns) => repl.send_in(ns, s)?,
+        None => repl.send(s)?,
+    }
+    recv_with(repl, handler)
+}
+
+/// Reads the responses to whatever was sent last, see `eval_with`.
+fn recv_with(
+    repl: &mut dyn Repl,
+    handler: &mut dyn FnMut(&Response, &str) -> Result<()>,
+) -> Result<Option<String>> {
+    let mut value = None;
+
+    loop {
+        let response = repl.recv()?;
+        handler(&response, &repl.get_ns())?;
+        match response {
+            Response::Value(s) => value = Some(s),
+            Response::Exception(_) => break,
+            Response::Done(opt) => {
+                if opt.is_some() {
+                    value = opt;
+                }
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(value)
+}
+
+fn eval(
+    repl: &mut dyn Repl,
+    s: &str,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<Option<String>> {
+    eval_with(repl, s, &mut |response: &Response, _: &str| {
+        print_response(response, &mut *out, &mut *err)
+    })
+}
+
+fn eval_logged(
+    repl: &mut dyn Repl,
+    ns: Option<&str>,
+    s: &str,
+    transcript: &mut Transcript,
+    out: &mut dyn Write,
+    err: &mut dyn Write,
+) -> Result<Option<String>> {
+    transcript.input(s)?;
+    eval_in_with(repl, ns, s, &mut |response: &Response, _: &str| {
+        transcript.response(response)?;
+        print_response(response, &mut *out, &mut *err)
+    })
+}
+
+fn format_duration(d: Duration) -> String {
+    format!("{:.3} ms", d.as_secs_f64() * 1000.0)
+}
+
+/// How results are shown at the prompt. Output beyond `max_chars` is kept in
+/// `truncated` for `:more`.
+struct Printing {
+    timing: bool,
+    max_chars: Option<usize>,
+    print_length: Option<usize>,
+    print_level: Option<usize>,
+    truncated: Option<String>,
+    colors: Colors,
+}
+
+/// Sets `*print-length*` and `*print-level*` in the session so that the server
+/// does not print more than asked for in the first place.
+fn apply_print_limits(repl: &mut dyn Repl, printing: &Printing) -> Result<()> {
+    if let Some(n) = printing.print_length {
+        eval_quiet(repl, &format!("(set! *print-length* {})", n))?;
+    }
+    if let Some(n) = printing.print_level {
+        eval_quiet(repl, &format!("(set! *print-level* {})", n))?;
+    }
+    Ok(())
+}
+
+/// Reprints the last result without the print limits.
+fn fetch_full_value(repl: &mut dyn Repl) -> Result<String> {
+    let s = keeping_results(repl, |repl| {
+        let form = "(binding [*print-length* nil *print-level* nil] (pr-str *1))";
+        eval_quiet(repl, &with_saved_results(form))
+    })?;
+    Ok(serde_edn::from_str(&s).unwrap_or(s))
+}
+
+fn page(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".into());
+    let mut parts = pager.split_whitespace();
+    let mut child = process::Command::new(parts.next().unwrap_or("less"))
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        // the pager may quit before reading everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+fn more(repl: &mut dyn Repl, printing: &Printing) -> Result<()> {
+    if let Some(text) = &printing.truncated {
+        page(text)
+    } else if printing.print_length.is_some() || printing.print_level.is_some() {
+        let full = fetch_full_value(repl)?;
+        page(&full)
+    } else {
+        println!("Nothing to page");
+        Ok(())
+    }
+}
+
+/// Validates and evaluates a form typed at the prompt. Output is cut at
+/// `max_chars` and the time from send to done is printed when timing is on.
+fn eval_form(
+    repl: &mut dyn Repl,
+    ns: Option<&str>,
+    code: &str,
+    transcript: &mut Transcript,
+    printing: &mut Printing,
+) -> Result<Option<String>> {
+    if !is_sendable(repl, code) {
+        println!("Not a valid form '{}'", code);
+        return Ok(None);
+    }
+
+    let mut out = stdout();
+    let mut err = stderr();
+    let max_chars = printing.max_chars;
+    let colors = printing.colors.clone();
+    let mut full = String::new();
+    let mut shown = 0;
+    let mut truncated = false;
+
+    let start = Instant::now();
+    transcript.input(code)?;
+    let value = eval_in_with(repl, ns, code, &mut |response: &Response, _: &str| {
+        transcript.response(response)?;
+        let (text, color) = match response {
+            Response::StdOut(s) => (s.clone(), None),
+            Response::Value(s) | Response::Done(Some(s)) => {
+                (format!("{}\n", s), colors.value.as_deref())
+            }
+            Response::StdErr(s) => {
+                return write_and_flush(&mut err, &paint(s, colors.err.as_deref()))
+            }
+            Response::Exception(s) => {
+                let s = format!("{}\n", s);
+                return write_and_flush(&mut out, &paint(&s, colors.exception.as_deref()));
+            }
+            _ => return print_response(response, &mut out, &mut err),
+        };
+        full.push_str(&text);
+
+        let len = text.chars().count();
+        match max_chars {
+            Some(max) if shown + len > max => {
+                if !truncated {
+                    let head: String = text.chars().take(max - shown).collect();
+                    write_and_flush(&mut out, &paint(&head, color))?;
+                    truncated = true;
+                }
+                shown = max;
+                Ok(())
+            }
+            _ => {
+                shown += len;
+                write_and_flush(&mut out, &paint(&text, color))
+            }
+        }
+    })?;
+
+    if truncated {
+        println!(
+            "\n... output truncated at {} chars, :more to page it all",
+            shown
+        );
+        printing.truncated = Some(full);
+    } else {
+        printing.truncated = None;
+    }
+    if printing.timing {
+        println!(";; {}", format_duration(start.elapsed()));
+    }
+
+    Ok(value)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let idx = (sorted.len() as f64 * p).ceil() as usize;
+    sorted[min(idx.max(1), sorted.len()) - 1]
+}
+
+/// Evaluates `form` `n` times with output discarded and prints the round-trip
+/// latencies.
+fn bench(repl: &mut dyn Repl, n: usize, form: &str) -> Result<()> {
+    if !is_sendable(repl, form) {
+        println!("Not a valid form '{}'", form);
+        return Ok(());
+    }
+
+    let mut times = Vec::with_capacity(n);
+    for i in 1..=n {
+        let start = Instant::now();
+        if eval(repl, form, &mut std::io::sink(), &mut std::io::sink())?.is_none() {
+            println!("Evaluation failed on run {}/{}", i, n);
+            return Ok(());
+        }
+        times.push(start.elapsed());
+    }
+    times.sort();
+
+    println!(
+        "{} runs: min {}, median {}, p95 {}, max {}",
+        n,
+        format_duration(times[0]),
+        format_duration(percentile(&times, 0.5)),
+        format_duration(percentile(&times, 0.95)),
+        format_duration(times[n - 1])
+    );
+
+    Ok(())
+}
+
+// Walks from the inspected root down `path`, where every step is an index
+// into the seq of the collection, and describes one page of what it finds.
+const INSPECT_FORM: &str = "(let [summary (fn [x] (binding [*print-length* 8 *print-level* 2] (pr-str x)))
+      v (reduce (fn [v i] (let [e (nth (seq v) i)] (if (map? v) (val e) e))) rclj.inspect/root PATH)]
+  {:type (str (type v))
+   :count (if (coll? v) (bounded-count 100000 v) 1)
+   :entries (if (coll? v)
+              (vec (for [e (take PAGE (drop OFFSET (seq v)))]
+                     (if (map? v)
+                       [(summary (key e)) (summary (val e)) (coll? (val e))]
+                       [nil (summary e) (coll? e)])))
+              [[nil (pr-str v) false]])})";
+
+/// One element of a collection, read from a `[label summary drillable]`
+/// vector.
+#[derive(Deserialize)]
+struct InspectEntry {
+    label: Option<String>,
+    summary: String,
+    drillable: bool,
+}
+
+#[derive(Deserialize)]
+struct InspectView {
+    #[serde(rename = "type")]
+    kind: String,
+    count: usize,
+    entries: Vec<InspectEntry>,
+}
+
+/// Evaluates `form` quietly, turning an exception into an error carrying
+/// whatever the server wrote to stderr.
+fn eval_quiet(repl: &mut dyn Repl, form: &str) -> Result<String> {
+    let mut err = vec![];
+    match eval(repl, form, &mut std::io::sink(), &mut err)? {
+        Some(value) => Ok(value),
+        None => bail!("{}", String::from_utf8_lossy(&err).trim()),
+    }
+}
+
+const SAVE_RESULTS_FORM: &str =
+    "(do (create-ns 'rclj.results) (intern 'rclj.results 'saved [*1 *2 *3]) nil)";
+
+// The value of the eval becomes *1 and what is set here is shifted once more
+// into *2 and *3.
+const RESTORE_RESULTS_FORM: &str =
+    "(let [[r1 r2 r3] rclj.results/saved] (set! *2 r3) (set! *1 r2) r1)";
+
+/// Runs `f`, whose evaluations would replace *1, *2 and *3 of the session,
+/// and puts back the results of the user's own evaluations afterwards. Forms
+/// evaluated by `f` see them through `with_saved_results`.
+fn keeping_results<T>(
+    repl: &mut dyn Repl,
+    f: impl FnOnce(&mut dyn Repl) -> Result<T>,
+) -> Result<T> {
+    eval_quiet(repl, SAVE_RESULTS_FORM)?;
+    let result = f(repl);
+    if let Err(e) = &result {
+        if is_connection_lost(e) {
+            return result;
+        }
+    }
+    eval_quiet(repl, RESTORE_RESULTS_FORM)?;
+    result
+}
+
+fn with_saved_results(form: &str) -> String {
+    format!("(let [[*1 *2 *3] rclj.results/saved] {})", form)
+}
+
+fn parse_inspect_view(s: &str) -> Result<InspectView> {
+    serde_edn::from_str(s).map_err(|e| anyhow!("Unexpected inspector response '{}': {}", s, e))
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() > width {
+        format!("{}...", s.chars().take(width).collect::<String>())
+    } else {
+        s.into()
+    }
+}
+
+/// Browses `form`, or the last result, one page at a time. Only the visible
+/// page is ever printed on the server, so huge or lazy values are fine.
+fn inspect(repl: &mut dyn Repl, form: Option<&str>) -> Result<()> {
+    let root = form.unwrap_or("*1");
+    if !is_sendable(repl, root) {
+        println!("Not a valid form '{}'", root);
+        return Ok(());
+    }
+    keeping_results(repl, |repl| browse(repl, root))
+}
+
+fn browse(repl: &mut dyn Repl, root: &str) -> Result<()> {
+    let setup = format!(
+        "(do (create-ns 'rclj.inspect) (intern 'rclj.inspect 'root {}) nil)",
+        with_saved_results(root)
+    );
+    if let Err(e) = eval_quiet(repl, &setup) {
+        if is_connection_lost(&e) {
+            return Err(e);
+        }
+        println!("Unable to inspect '{}': {}", root, e);
+        return Ok(());
+    }
+
+    let mut path: Vec<usize> = vec![];
+    let mut offsets: Vec<usize> = vec![0];
+    let mut rl = Editor::<()>::new();
+
+    loop {
+        let offset = *offsets.last().unwrap_or(&0);
+        let path_str = path
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        let form = INSPECT_FORM
+            .replace("PATH", &format!("[{}]", path_str))
+            .replace("PAGE", &INSPECT_PAGE.to_string())
+            .replace("OFFSET", &offset.to_string());
+        let view = match eval_quiet(repl, &form).and_then(|s| parse_inspect_view(&s)) {
+            Ok(view) => view,
+            Err(e) if is_connection_lost(&e) => return Err(e),
+            Err(e) => {
+                println!("Inspector failed: {}", e);
+                return Ok(());
+            }
+        };
+
+        println!("\n[{}] {}, {} entries", path_str, view.kind, view.count);
+        for (i, entry) in view.entries.iter().enumerate() {
+            let label = match &entry.label {
+                Some(label) => format!("{} ", truncate(label, INSPECT_SUMMARY_WIDTH / 3)),
+                None => "".into(),
+            };
+            println!(
+                "{:>6}{} {}{}",
+                offset + i,
+                if entry.drillable { "+" } else { " " },
+                label,
+                truncate(&entry.summary, INSPECT_SUMMARY_WIDTH)
+            );
+        }
+        if view.count > offset + view.entries.len() || offset > 0 {
+            println!(
+                "showing {}-{} of {}",
+                offset,
+                offset + view.entries.len(),
+                view.count
+            );
+        }
+
+        let line = match rl.readline("inspect [index, n(ext), p(rev), u(p), q(uit)]> ") {
+            Ok(line) => line.trim().to_string(),
+            Err(_) => break,
+        };
+        match line.as_str() {
+            "q" => break,
+            "n" if offset + INSPECT_PAGE < view.count => {
+                offsets.pop();
+                offsets.push(offset + INSPECT_PAGE);
+            }
+            "p" => {
+                offsets.pop();
+                offsets.push(offset.saturating_sub(INSPECT_PAGE));
+            }
+            "u" if !path.is_empty() => {
+                path.pop();
+                offsets.pop();
+            }
+            _ => match line.parse::<usize>() {
+                Ok(i) if i >= offset && i - offset < view.entries.len() => {
+                    if view.entries[i - offset].drillable {
+                        path.push(i);
+                        offsets.push(0);
+                    } else {
+                        println!("{}", view.entries[i - offset].summary);
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    Ok(())
+}
+
+fn source_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            source_files(&entry?.path(), files)?;
+        }
+    } else if let Some(ext) = path.extension() {
+        if ext == "clj" || ext == "cljc" {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Tracks modification times of the `.clj`/`.cljc` files under `roots`.
+struct Watcher {
+    roots: Vec<PathBuf>,
+    mtimes: HashMap<PathBuf, SystemTime>,
+}
+
+impl Watcher {
+    fn new(roots: Vec<PathBuf>) -> Result<Watcher> {
+        let mut watcher = Watcher {
+            roots,
+            mtimes: HashMap::new(),
+        };
+        watcher.changed()?;
+        Ok(watcher)
+    }
+
+    /// Files that are new or modified since the last call.
+    fn changed(&mut self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for root in self.roots.iter() {
+            source_files(root, &mut files)?;
+        }
+
+        let mut changed = vec![];
+        let mut mtimes = HashMap::new();
+        for file in files {
+            // the file may be gone already, editors often save via rename
+            let mtime = match fs::metadata(&file).and_then(|meta| meta.modified()) {
+                Ok(mtime) => mtime,
+                Err(_) => continue,
+            };
+            if self.mtimes.get(&file) != Some(&mtime) {
+                changed.push(file.clone());
+            }
+            mtimes.insert(file, mtime);
+        }
+        self.mtimes = mtimes;
+        changed.sort();
+
+        Ok(changed)
+    }
+}
+
+/// Loads `path` through the repl. Returns the error output when loading
+/// failed, also when the file could not be read.
+fn reload_file(repl: &mut dyn Repl, path: &Path) -> Result<Option<String>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return Ok(Some(format!("Unable to read file: {}", e))),
+    };
+    let mut errors = String::new();
+
+    repl.send_file(&path.to_string_lossy(), &contents)?;
+    let value = recv_with(repl, &mut |response: &Response, _: &str| {
+        match response {
+            Response::StdErr(s) => errors.push_str(s),
+            Response::Exception(s) => errors.push_str(&format!("{}\n", s)),
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    match value {
+        Some(_) => Ok(None),
+        None => Ok(Some(errors.trim().to_string())),
+    }
+}
+
+/// Reloads changed files until `stop` is set, logging one line per file.
+fn watch(
+    repl: &mut dyn Repl,
+    roots: Vec<PathBuf>,
+    stop: &AtomicBool,
+    log: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let mut watcher = Watcher::new(roots)?;
+
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+
+        for file in watcher.changed()? {
+            let now = chrono::Local::now().format("%H:%M:%S");
+            let start = Instant::now();
+            match reload_file(repl, &file)? {
+                None => log(&format!(
+                    "[{}] reloaded {} ({})",
+                    now,
+                    file.display(),
+                    format_duration(start.elapsed())
+                )),
+                Some(errors) => log(&format!(
+                    "[{}] FAILED   {}\n{}",
+                    now,
+                    file.display(),
+                    errors
+                )),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const NAMESPACES_FORM: &str = "(clojure.string/join \" \" (sort (map str (all-ns))))";
+
+fn namespaces(repl: &mut dyn Repl) -> Result<Vec<String>> {
+    let names: String = serde_edn::from_str(&eval_quiet(repl, NAMESPACES_FORM)?)?;
+    Ok(names.split_whitespace().map(String::from).collect())
+}
+
+/// Prints the namespaces starting with `prefix`, fetched over a connection
+/// of its own like in `refresh_namespaces`.
+fn print_namespaces(endpoint: &Endpoint, prefix: &str) -> Result<()> {
+    let mut repl = get_repl(endpoint)?;
+    let names = namespaces(&mut *repl);
+    let _ = repl.quit();
+    for ns in names? {
+        if ns.starts_with(prefix) {
+            println!("{}", ns);
+        }
+    }
+    Ok(())
+}
+
+/// Updates the namespaces used for tab completion. They are fetched over a
+/// connection of their own so that *1 and friends of the session stay put.
+fn refresh_namespaces(endpoint: &Endpoint, target: &Arc<Mutex<Vec<String>>>) {
+    let endpoint = endpoint.clone();
+    let target = target.clone();
+
+    thread::spawn(move || {
+        if let Ok(mut repl) = get_repl(&endpoint) {
+            if let Ok(names) = namespaces(&mut *repl) {
+                *target.lock().unwrap() = names;
+            }
+            let _ = repl.quit();
+        }
+    });
+}
+
+/// Watches `root` on a connection of its own, so that the prompt stays
+/// usable meanwhile.
+fn spawn_watch(endpoint: &Endpoint, root: &str) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let endpoint = endpoint.clone();
+    let root = PathBuf::from(root);
+
+    thread::spawn(move || {
+        let result = get_repl(&endpoint).and_then(|mut repl| {
+            let mut log = |msg: &str| eprintln!("{}", msg);
+            let result = watch(&mut *repl, vec![root.clone()], &thread_stop, &mut log);
+            let _ = repl.quit();
+            result
+        });
+        if let Err(e) = result {
+            eprintln!("Watching {} stopped: {}", root.display(), e);
+        }
+    });
+
+    stop
+}
+
+/// Whether `input` has a form that can create namespaces, like `ns`,
+/// `require` or `load`.
+fn loads_namespaces(input: &str) -> bool {
+    split_forms(input).iter().any(|form| {
+        let head = form
+            .trim_start()
+            .strip_prefix('(')
+            .and_then(|form| form.split(|c: char| c.is_whitespace() || c == ')').next());
+        matches!(head, Some("ns" | "require" | "load" | "load-file"))
+    })
+}
+
+const TEST_FORM: &str = r#"
+(do (require 'clojure.test NAMESPACES)
+  (let [failures (atom [])
+        report clojure.test/report
+        summary (binding [clojure.test/report
+                          (fn [m]
+                            (when (#{:fail :error} (:type m))
+                              (swap! failures conj
+                                     {:type (:type m)
+                                      :var (str (some-> clojure.test/*testing-vars* first meta :name))
+                                      :context (clojure.test/testing-contexts-str)
+                                      :message (str (:message m))
+                                      :expected (pr-str (:expected m))
+                                      :actual (pr-str (:actual m))
+                                      :file (str (:file m))
+                                      :line (str (:line m))}))
+                            (report m))]
+                  (clojure.test/run-tests NAMESPACES))]
+    (binding [*print-length* nil *print-level* nil]
+      (pr-str {:summary summary :failures @failures}))))
+"#;
+
+#[derive(Default, Deserialize)]
+struct TestFailure {
+    #[serde(rename = "type")]
+    kind: String,
+    var: String,
+    context: String,
+    message: String,
+    expected: String,
+    actual: String,
+    file: String,
+    line: String,
+}
+
+#[derive(Default)]
+struct TestReport {
+    tests: usize,
+    pass: usize,
+    fail: usize,
+    error: usize,
+    failures: Vec<TestFailure>,
+}
+
+/// What TEST_FORM prints, the summary being the one of `run-tests`.
+#[derive(Deserialize)]
+struct TestResults {
+    summary: TestSummary,
+    failures: Vec<TestFailure>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TestSummary {
+    test: usize,
+    pass: usize,
+    fail: usize,
+    error: usize,
+}
+
+impl TestReport {
+    fn add_cider_results(&mut self, var: &str, results: &[bencode_rs::Value]) {
+        let field = |result: &bencode_rs::Value, key: &str| {
+            bencode_get(result, key)
+                .map(bencode_text)
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+
+        self.tests += 1;
+        for result in results {
+            match field(result, "type").as_str() {
+                "pass" => self.pass += 1,
+                kind => {
+                    if kind == "error" {
+                        self.error += 1;
+                    } else {
+                        self.fail += 1;
+                    }
+                    self.failures.push(TestFailure {
+                        kind: kind.into(),
+                        var: format!("{}/{}", field(result, "ns"), var),
+                        context: field(result, "context"),
+                        message: field(result, "message"),
+                        expected: field(result, "expected"),
+                        actual: field(result, "actual"),
+                        file: field(result, "file"),
+                        line: field(result, "line"),
+                    });
+                }
+            }
+        }
+    }
+
+    fn parse(s: &str) -> Result<TestReport> {
+        let results: TestResults = serde_edn::from_str(s)
+            .and_then(|s: String| serde_edn::from_str(&s))
+            .map_err(|e| anyhow!("Unexpected test response '{}': {}", s, e))?;
+        Ok(TestReport {
+            tests: results.summary.test,
+            pass: results.summary.pass,
+            fail: results.summary.fail,
+            error: results.summary.error,
+            failures: results.failures,
+        })
+    }
+
+    fn failed(&self) -> bool {
+        self.fail + self.error > 0
+    }
+
+    fn print(&self, colors: &Colors) {
+        for failure in self.failures.iter() {
+            let kind = if failure.kind == "error" {
+                "ERROR"
+            } else {
+                "FAIL"
+            };
+            println!(
+                "\n{} in {} ({}:{})",
+                paint(kind, colors.exception.as_deref()),
+                failure.var,
+                failure.file,
+                failure.line
+            );
+            for line in [&failure.context, &failure.message].iter() {
+                if !line.is_empty() {
+                    println!("{}", line);
+                }
+            }
+            println!("expected: {}", failure.expected);
+            println!("  actual: {}", failure.actual);
+        }
+
+        let summary = format!(
+            "Ran {} tests containing {} assertions. {} failures, {} errors.",
+            self.tests,
+            self.pass + self.fail + self.error,
+            self.fail,
+            self.error
+        );
+        let color = if self.failed() {
+            colors.exception.as_deref()
+        } else {
+            colors.value.as_deref()
+        };
+        println!("\n{}", paint(&summary, color));
+    }
+}
+
+/// Runs the tests of `namespaces`, or of the current namespace when none
+/// are given.
+fn run_tests(repl: &mut dyn Repl, namespaces: &[String]) -> Result<TestReport> {
+    let namespaces = if namespaces.is_empty() {
+        vec![repl.get_ns()]
+    } else {
+        namespaces.to_vec()
+    };
+
+    if let Some(report) = repl.test_op(&namespaces)? {
+        return Ok(report);
+    }
+
+    let quoted: Vec<String> = namespaces.iter().map(|ns| format!("'{}", ns)).collect();
+    let form = TEST_FORM.replace("NAMESPACES", &quoted.join(" "));
+    TestReport::parse(&eval_quiet(repl, &form)?)
+}
+
+fn reconnect(endpoint: &Endpoint, ns: &str, log: &mut dyn FnMut(&str)) -> Result<Box<dyn Repl>> {
+    let mut delay = 250;
+
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        thread::sleep(Duration::from_millis(delay));
+        delay = min(delay * 2, RECONNECT_MAX_DELAY_MS);
+
+        let restored = get_repl(endpoint).and_then(|mut repl| {
+            let form = format!("(in-ns '{})", ns);
+            eval(
+                &mut *repl,
+                &form,
+                &mut std::io::sink(),
+                &mut std::io::sink(),
+            )?;
+            Ok(repl)
+        });
+        match restored {
+            Ok(repl) => {
+                log(&format!(
+                    "Reconnected to {} at {} (ns {})",
+                    repl.repl_type(),
+                    endpoint,
+                    repl.get_ns()
+                ));
+                return Ok(repl);
+            }
+            Err(e) => {
+                log(&format!(
+                    "Reconnect attempt {}/{} failed: {}",
+                    attempt, RECONNECT_ATTEMPTS, e
+                ));
+            }
+        }
+    }
+
+    bail!("Unable to reconnect to {}", endpoint)
+}
+
+fn main_loop(
+    mut repl: Box<dyn Repl>,
+    endpoint: &Endpoint,
+    transcript: &mut Transcript,
+    mut printing: Printing,
+    config: &Config,
+    init: &[String],
+) -> Result<()> {
+    let mut runtime = Runtime::Clj;
+    let mut clj_ns = repl.get_ns();
+    let mut evaluated: Vec<String> = vec![];
+    let mut rl = config.editor();
+    let namespaces = rl
+        .helper()
+        .map(|helper| helper.namespaces.clone())
+        .unwrap_or_default();
+    refresh_namespaces(endpoint, &namespaces);
+    let mut watches: Vec<(String, Arc<AtomicBool>)> = vec![];
+
+    loop {
+        let ns = repl.get_ns();
+        let prompt = match &runtime {
+            Runtime::Clj => ns.clone(),
+            Runtime::Cljs(build) => format!("[cljs {}] {}", build, ns),
+        };
+        let prompt = paint(&prompt, config.colors.prompt.as_deref());
+        let s = match readline(&mut rl, &prompt)? {
+            Some(s) => s,
+            None => {
+                repl.quit()?;
+                break;
+            }
+        };
+
+        let input = config.expand_command(&s).unwrap_or_else(|| s.clone());
+        let command = parse_command(&s);
+        let result = match &command {
+            Some(Command::Cljs(arg)) => {
+                if let Runtime::Cljs(build) = &runtime {
+                    println!("Already in ClojureScript REPL '{}', use :clj first", build);
+                    continue;
+                }
+                eval_form(
+                    &mut *repl,
+                    None,
+                    &cljs_repl_form(arg),
+                    transcript,
+                    &mut printing,
+                )
+            }
+            Some(Command::Clj) => {
+                if let Runtime::Clj = runtime {
+                    println!("Not in a ClojureScript REPL");
+                    continue;
+                }
+                eval_form(&mut *repl, None, ":cljs/quit", transcript, &mut printing)
+            }
+            Some(Command::Describe) => {
+                print_capabilities(&*repl);
+                continue;
+            }
+            Some(Command::Save(path)) => {
+                match save_forms(path, &evaluated) {
+                    Ok(()) => println!("Saved {} forms to {}", evaluated.len(), path),
+                    Err(e) => println!("Unable to save forms to {}: {}", path, e),
+                }
+                continue;
+            }
+            Some(Command::Timing) => {
+                printing.timing = !printing.timing;
+                println!("Timing {}", if printing.timing { "on" } else { "off" });
+                continue;
+            }
+            Some(Command::Bench(n, form)) => bench(&mut *repl, *n, form).map(|_| None),
+            Some(Command::Inspect(form)) => inspect(&mut *repl, form.as_deref()).map(|_| None),
+            Some(Command::More) => more(&mut *repl, &printing).map(|_| None),
+            Some(Command::Watch(path)) => {
+                if !Path::new(path).exists() {
+                    println!("No such file or directory: {}", path);
+                } else {
+                    watches.push((path.clone(), spawn_watch(endpoint, path)));
+                    println!("Watching {}, stop with :unwatch", path);
+                }
+                continue;
+            }
+            Some(Command::Test(namespaces)) => run_tests(&mut *repl, namespaces)
+                .map(|report| report.print(&printing.colors))
+                .map(|_| None),
+            Some(Command::Unwatch) => {
+                for (path, stop) in watches.drain(..) {
+                    stop.store(true, Ordering::Relaxed);
+                    println!("Stopped watching {}", path);
+                }
+                continue;
+            }
+            Some(Command::In(ns, form)) => eval_form(
+                &mut *repl,
+                Some(ns.as_str()),
+                form,
+                transcript,
+                &mut printing,
+            ),
+            Some(Command::Ns(prefix)) => {
+                let result = print_namespaces(endpoint, prefix);
+                refresh_namespaces(endpoint, &namespaces);
+                result.map(|_| None)
+            }
+            None => eval_form(&mut *repl, None, &input, transcript, &mut printing),
+        };
+
+        match result {
+            Ok(Some(value)) => {
+                if loads_namespaces(&input) {
+                    refresh_namespaces(endpoint, &namespaces);
+                }
+                if command.is_none() {
+                    evaluated.push(input);
+                }
+                if value == ":cljs/quit" {
+                    if let Runtime::Cljs(_) = runtime {
+                        println!("Returned to Clojure REPL");
+                    }
+                    runtime = Runtime::Clj;
+                } else if let Some(Command::Cljs(arg)) = command {
+                    clj_ns = ns;
+                    runtime = Runtime::Cljs(arg.trim_start_matches(':').into());
+                }
+            }
+            Ok(None) => {}
+            Err(e) if !is_connection_lost(&e) => println!("{}", e),
+            Err(e) => {
+                println!("\n{}, evaluation of '{}' was lost", e, s);
+                let restore_ns = match runtime {
+                    Runtime::Clj => ns,
+                    Runtime::Cljs(_) => {
+                        println!("ClojureScript REPL was lost, returning to Clojure");
+                        clj_ns.clone()
+                    }
+                };
+                runtime = Runtime::Clj;
+                println!("Reconnecting to {}...", endpoint);
+                repl = reconnect(endpoint, &restore_ns, &mut |msg: &str| println!("{}", msg))?;
+                if let Err(e) = apply_print_limits(&mut *repl, &printing) {
+                    eprintln!("Unable to set print limits: {}", e);
+                }
+                run_init_forms(&mut *repl, init)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn json_event(id: &str, kind: &str, ns: &str, val: Option<&str>, elapsed: Duration) -> String {
+    let mut event = serde_json::json!({
+        "id": id,
+        "kind": kind,
+        "ns": ns,
+        "ms": elapsed.as_secs_f64() * 1000.0,
+    });
+    if let Some(val) = val {
+        event["val"] = serde_json::Value::String(val.into());
+    }
+
+    format!("{}\n", event)
+}
+
+fn write_json_failure(
+    out: &mut dyn Write,
+    id: &str,
+    ns: &str,
+    msg: &str,
+    start: Instant,
+) -> Result<()> {
+    write_and_flush(
+        out,
+        &json_event(id, "exception", ns, Some(msg), start.elapsed()),
+    )?;
+    write_and_flush(out, &json_event(id, "done", ns, None, start.elapsed()))
+}
+
+// Every request ends with a 'done' event, also the ones ending in exception.
+fn response_events(response: &Response) -> Vec<(&str, Option<&str>)> {
+    match response {
+        Response::StdOut(s) => vec![("out", Some(s.as_str()))],
+        Response::StdErr(s) => vec![("err", Some(s.as_str()))],
+        Response::Value(s) => vec![("value", Some(s.as_str()))],
+        Response::Tap(s) => vec![("tap", Some(s.as_str()))],
+        Response::Exception(s) => vec![("exception", Some(s.as_str())), ("done", None)],
+        Response::Done(Some(s)) => vec![("value", Some(s.as_str())), ("done", None)],
+        Response::Done(None) => vec![("done", None)],
+        Response::Other(_) => vec![],
+    }
+}
+
+/// Lines that are JSON objects with a "code" key are requests, optionally
+/// carrying their own "id". Anything else is taken as a form.
+fn parse_json_request(line: &str, counter: usize) -> (String, String) {
+    if let Ok(serde_json::Value::Object(map)) = serde_json::from_str::<serde_json::Value>(line) {
+        if let Some(serde_json::Value::String(code)) = map.get("code") {
+            let id = match map.get("id") {
+                Some(serde_json::Value::String(id)) => id.clone(),
+                Some(serde_json::Value::Number(id)) => id.to_string(),
+                _ => counter.to_string(),
+            };
+            return (id, code.clone());
+        }
+    }
+
+    (counter.to_string(), line.to_string())
+}
+
+fn json_loop(
+    mut repl: Box<dyn Repl>,
+    eval_ns: Option<&str>,
+    endpoint: &Endpoint,
+    transcript: &mut Transcript,
+) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut out = stdout();
+    let mut counter = 0;
+    let mut pending = String::new();
+
+    for line in stdin.lock().lines() {
+        // a form may span lines, it is sent once its delimiters are closed
+        pending.push_str(&line?);
+        pending.push('\n');
+        if delimiter_depth(&pending) > 0 {
+            continue;
+        }
+        let text = std::mem::take(&mut pending);
+        let line = text.trim();
+        if line.is_empty() {
+            continue;
+        }
+        counter += 1;
+
+        let (id, code) = parse_json_request(line, counter);
+        let start = Instant::now();
+        if !is_sendable(&*repl, &code) {
+            let msg = format!("Not a valid form '{}'", code);
+            write_json_failure(&mut out, &id, &repl.get_ns(), &msg, start)?;
+            continue;
+        }
+
+        let ns = repl.get_ns();
+        transcript.input(&code)?;
+        let result = eval_in_with(
+            &mut *repl,
+            eval_ns,
+            &code,
+            &mut |response: &Response, ns: &str| {
+                transcript.response(response)?;
+                for (kind, val) in response_events(response) {
+                    write_and_flush(&mut out, &json_event(&id, kind, ns, val, start.elapsed()))?;
+                }
+                Ok(())
+            },
+        );
+
+        if let Err(e) = result {
+            if !is_connection_lost(&e) {
+                return Err(e);
+            }
+            let msg = format!("{}, evaluation was lost", e);
+            write_json_failure(&mut out, &id, &ns, &msg, start)?;
+            repl = reconnect(endpoint, &ns, &mut |msg: &str| eprintln!("{}", msg))?;
+        }
+    }
+
+    if !pending.trim().is_empty() {
+        let id = (counter + 1).to_string();
+        let msg = format!("Incomplete form '{}'", pending.trim());
+        write_json_failure(&mut out, &id, &repl.get_ns(), &msg, Instant::now())?;
+    }
+
+    repl.quit()
+}
+
+enum TuiEvent {
+    Connected(String, String),
+    Output(String, String, String),
+    Server(String),
+    Lost(String),
+}
+
+/// Scrollable list of lines, wrapped to the pane width when drawn.
+#[derive(Default)]
+struct Pane {
+    lines: Vec<(String, Attr)>,
+    open: bool,
+    scroll: usize,
+}
+
+impl Pane {
+    fn append(&mut self, text: &str, attr: Attr) {
+        if text.is_empty() {
+            return;
+        }
+        let mut parts = text.split('\n');
+        if let Some(first) = parts.next() {
+            match self.lines.last_mut() {
+                Some((line, _)) if self.open => line.push_str(first),
+                _ => self.lines.push((first.to_string(), attr)),
+            }
+        }
+        for part in parts {
+            self.lines.push((part.to_string(), attr));
+        }
+        self.open = true;
+        if text.ends_with('\n') {
+            self.lines.pop();
+            self.open = false;
+        }
+        if self.lines.len() > TUI_MAX_LINES {
+            self.lines.drain(..self.lines.len() - TUI_MAX_LINES);
+        }
+    }
+
+    fn line(&mut self, text: &str, attr: Attr) {
+        self.open = false;
+        self.append(&format!("{}\n", text), attr);
+    }
+
+    fn scroll_up(&mut self) {
+        self.scroll = min(self.scroll + TUI_SCROLL_STEP, self.lines.len());
+    }
+
+    fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(TUI_SCROLL_STEP);
+    }
+}
+
+impl Draw for Pane {
+    fn draw(&self, canvas: &mut dyn Canvas) -> DrawResult<()> {
+        let (width, height) = canvas.size()?;
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let mut rows: Vec<(String, Attr)> = vec![];
+        for (line, attr) in self.lines.iter() {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.is_empty() {
+                rows.push(("".into(), *attr));
+            }
+            for chunk in chars.chunks(width) {
+                rows.push((chunk.iter().collect(), *attr));
+            }
+        }
+
+        let end = rows.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        for (row, (text, attr)) in rows[start..end].iter().enumerate() {
+            canvas.print_with_attr(row, 0, text, *attr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Widget for Pane {}
+
+#[derive(Default)]
+struct InputEditor {
+    prompt: String,
+    input: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    history_pos: usize,
+}
+
+impl InputEditor {
+    fn text(&self) -> String {
+        self.input.iter().collect()
+    }
+
+    fn set_text(&mut self, text: &str) {
+        self.input = text.chars().collect();
+        self.cursor = self.input.len();
+    }
+
+    fn height(&self) -> usize {
+        self.input.iter().filter(|c| **c == '\n').count() + 1
+    }
+
+    fn insert(&mut self, c: char) {
+        self.input.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.input.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.input.len() {
+            self.input.remove(self.cursor);
+        }
+    }
+
+    fn history_prev(&mut self) {
+        if self.history_pos > 0 {
+            self.history_pos -= 1;
+            let text = self.history[self.history_pos].clone();
+            self.set_text(&text);
+        }
+    }
+
+    fn history_next(&mut self) {
+        if self.history_pos + 1 < self.history.len() {
+            self.history_pos += 1;
+            let text = self.history[self.history_pos].clone();
+            self.set_text(&text);
+        } else {
+            self.history_pos = self.history.len();
+            self.set_text("");
+        }
+    }
+
+    fn take(&mut self) -> String {
+        let text = self.text();
+        self.history.push(text.clone());
+        self.history_pos = self.history.len();
+        self.set_text("");
+        text
+    }
+}
+
+impl Draw for InputEditor {
+    fn draw(&self, canvas: &mut dyn Canvas) -> DrawResult<()> {
+        let prompt = format!("{}=> ", self.prompt);
+        let indent = " ".repeat(prompt.chars().count());
+        let (mut row, mut col) = (0, prompt.chars().count());
+        canvas.print(0, 0, &prompt)?;
+
+        for (i, line) in self.text().split('\n').enumerate() {
+            if i > 0 {
+                canvas.print(i, 0, &indent)?;
+            }
+            canvas.print(i, indent.len(), line)?;
+        }
+        for c in self.input[..self.cursor].iter() {
+            if *c == '\n' {
+                row += 1;
+                col = indent.len();
+            } else {
+                col += 1;
+            }
+        }
+        canvas.set_cursor(row, col)?;
+
+        Ok(())
+    }
+}
+
+impl Widget for InputEditor {}
+
+struct StatusBar(String);
+
+impl Draw for StatusBar {
+    fn draw(&self, canvas: &mut dyn Canvas) -> DrawResult<()> {
+        let (width, _) = canvas.size()?;
+        let attr = Attr {
+            effect: Effect::REVERSE,
+            ..Attr::default()
+        };
+        canvas.print_with_attr(0, 0, &format!("{:<1$}", self.0, width), attr)?;
+        Ok(())
+    }
+}
+
+impl Widget for StatusBar {}
+
+/// Owns the repl connection and evaluates forms received from the ui thread,
+/// posting every response back as a terminal event so that output arriving
+/// late never writes over the editor.
+fn tui_worker(
+    endpoint: Endpoint,
+    init: Vec<String>,
+    term: Arc<Term<TuiEvent>>,
+    forms: Receiver<String>,
+) {
+    let send = |event: TuiEvent| {
+        let _ = term.send_event(TermEvent::User(event));
+    };
+    let output = |kind: &str, text: &str, ns: &str| {
+        send(TuiEvent::Output(kind.into(), text.into(), ns.into()));
+    };
+
+    let mut repl = match get_repl(&endpoint) {
+        Ok(repl) => repl,
+        Err(e) => {
+            send(TuiEvent::Lost(format!("Unable to connect: {}", e)));
+            return;
+        }
+    };
+    for form in init.iter() {
+        if let Err(e) = eval_quiet(&mut *repl, form) {
+            output("err", &format!("Init form '{}' failed: {}\n", form, e), "");
+        }
+    }
+    send(TuiEvent::Connected(repl.repl_type(), repl.get_ns()));
+
+    loop {
+        let ns = repl.get_ns();
+        let result = match forms.try_recv() {
+            Ok(code) => {
+                if !is_sendable(&*repl, &code) {
+                    output("exception", &format!("Not a valid form '{}'", code), &ns);
+                    output("done", "", &ns);
+                    continue;
+                }
+                let result = eval_with(&mut *repl, &code, &mut |response: &Response, ns: &str| {
+                    for (kind, val) in response_events(response) {
+                        output(kind, val.unwrap_or(""), ns);
+                    }
+                    Ok(())
+                });
+                if let Err(e) = &result {
+                    output("exception", &format!("{}, evaluation was lost", e), &ns);
+                    output("done", "", &ns);
+                }
+                result.map(|_| ())
+            }
+            // between requests the connection is watched for output that
+            // arrives late, like that of a future
+            Err(TryRecvError::Empty) => match repl.poll(Duration::from_millis(TUI_POLL_MS)) {
+                Ok(Some(response)) => {
+                    for (kind, val) in response_events(&response) {
+                        if kind != "done" {
+                            output(kind, val.unwrap_or(""), &repl.get_ns());
+                        }
+                    }
+                    Ok(())
+                }
+                Ok(None) => Ok(()),
+                Err(e) => {
+                    output("err", &format!("{}\n", e), &ns);
+                    Err(e)
+                }
+            },
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        if let Err(e) = result {
+            if !is_connection_lost(&e) {
+                continue;
+            }
+            match reconnect(&endpoint, &ns, &mut |msg: &str| output("err", msg, &ns)) {
+                Ok(new_repl) => {
+                    repl = new_repl;
+                    send(TuiEvent::Connected(repl.repl_type(), repl.get_ns()));
+                }
+                Err(e) => {
+                    send(TuiEvent::Lost(e.to_string()));
+                    return;
+                }
+            }
+        }
+    }
+
+    let _ = repl.quit();
+}
+
+fn tui(endpoint: &Endpoint, init: &[String], server: Option<Receiver<String>>) -> Result<()> {
+    let term: Arc<Term<TuiEvent>> =
+        Arc::new(Term::new().map_err(|e| anyhow!("Unable to start tui: {}", e))?);
+    let (tx, rx) = channel();
+
+    if let Some(server) = server {
+        let server_term = term.clone();
+        thread::spawn(move || {
+            for line in server {
+                let _ = server_term.send_event(TermEvent::User(TuiEvent::Server(line)));
+            }
+        });
+    }
+
+    let worker_term = term.clone();
+    let worker_endpoint = endpoint.clone();
+    let worker_init = init.to_vec();
+    thread::spawn(move || tui_worker(worker_endpoint, worker_init, worker_term, rx));
+
+    let red = Attr::default().fg(Color::RED);
+    let yellow = Attr::default().fg(Color::YELLOW);
+    let dim = Attr {
+        effect: Effect::DIM,
+        ..Attr::default()
+    };
+
+    let mut results = Pane::default();
+    let mut output = Pane::default();
+    let mut editor = InputEditor::default();
+    let mut repl_type = "connecting".to_string();
+    let mut pending = 0;
+    editor.prompt = "user".into();
+
+    while let Ok(ev) = term.poll_event() {
+        match ev {
+            TermEvent::Key(Key::Ctrl('c')) => break,
+            TermEvent::Key(Key::Ctrl('d')) if editor.input.is_empty() => break,
+            TermEvent::Key(Key::Enter) => {
+                let text = editor.text();
+                if delimiter_depth(&text) > 0 {
+                    editor.insert('\n');
+                } else if !text.trim().is_empty() {
+                    let code = editor.take();
+                    if parse_command(code.trim()).is_some() {
+                        results.line(&format!("{} is not available in tui mode", code), red);
+                    } else {
+                        results.line(&format!("{}=> {}", editor.prompt, code), dim);
+                        results.scroll = 0;
+                        pending += 1;
+                        tx.send(code)?;
+                    }
+                }
+            }
+            TermEvent::Key(Key::Char(c)) => editor.insert(c),
+            TermEvent::Key(Key::Tab) => editor.insert(' '),
+            TermEvent::Key(Key::Backspace) => editor.backspace(),
+            TermEvent::Key(Key::Delete) => editor.delete(),
+            TermEvent::Key(Key::Left) => editor.cursor = editor.cursor.saturating_sub(1),
+            TermEvent::Key(Key::Right) => {
+                editor.cursor = min(editor.cursor + 1, editor.input.len())
+            }
+            TermEvent::Key(Key::Home) | TermEvent::Key(Key::Ctrl('a')) => editor.cursor = 0,
+            TermEvent::Key(Key::End) | TermEvent::Key(Key::Ctrl('e')) => {
+                editor.cursor = editor.input.len()
+            }
+            TermEvent::Key(Key::Up) => editor.history_prev(),
+            TermEvent::Key(Key::Down) => editor.history_next(),
+            TermEvent::Key(Key::PageUp) => results.scroll_up(),
+            TermEvent::Key(Key::PageDown) => results.scroll_down(),
+            TermEvent::Key(Key::AltPageUp) => output.scroll_up(),
+            TermEvent::Key(Key::AltPageDown) => output.scroll_down(),
+            TermEvent::User(TuiEvent::Connected(rtype, ns)) => {
+                repl_type = rtype;
+                editor.prompt = ns;
+            }
+            TermEvent::User(TuiEvent::Output(kind, text, ns)) => {
+                editor.prompt = ns;
+                match kind.as_str() {
+                    "out" => output.append(&text, Attr::default()),
+                    "err" => output.append(&text, red),
+                    "tap" => output.line(&format!("tap> {}", text), yellow),
+                    "value" => results.line(&text, Attr::default()),
+                    "exception" => results.line(&text, red),
+                    "done" => pending -= 1,
+                    _ => {}
+                }
+            }
+            TermEvent::User(TuiEvent::Server(line)) => output.line(&line, dim),
+            TermEvent::User(TuiEvent::Lost(msg)) => {
+                repl_type = "disconnected".into();
+                results.line(&msg, red);
+            }
+            _ => {}
+        }
+
+        let status = StatusBar(format!(
+            " {} {} | ns {} | {} | PgUp/PgDn scroll, Ctrl-C quit",
+            repl_type,
+            endpoint,
+            editor.prompt,
+            if pending > 0 { "evaluating" } else { "idle" }
+        ));
+        let screen = VSplit::default()
+            .split(
+                HSplit::default()
+                    .split(Win::new(&results).border(true).title("Results"))
+                    .split(
+                        Win::new(&output)
+                            .border(true)
+                            .title("Output")
+                            .basis(Size::Percent(40)),
+                    ),
+            )
+            .split(Win::new(&editor).border(true).basis(editor.height() + 2))
+            .split(Win::new(&status).basis(1));
+
+        let _ = term.draw(&screen);
+        let _ = term.present();
+    }
+
+    Ok(())
+}
+
+/// Evaluates all forms in `path`, e.g. a file written with `:save`, stopping
+/// at the first one that fails.
+fn script(
+    mut repl: Box<dyn Repl>,
+    ns: Option<&str>,
+    path: &Path,
+    transcript: &mut Transcript,
+) -> Result<()> {
+    let mut out = stdout();
+    let mut err = stderr();
+
+    for form in split_forms(&fs::read_to_string(path)?) {
+        if !is_sendable(&*repl, &form) {
+            bail!("Not a valid form '{}'", form);
+        }
+        if eval_logged(&mut *repl, ns, &form, transcript, &mut out, &mut err)?.is_none() {
+            bail!("Evaluation of '{}' failed", form);
+        }
+    }
+
+    repl.quit()
+}
+
+/// Copies everything read from `reader` to `writer` as it is read, so the
+/// proxy relays the exact bytes while decoding its own copy.
+struct Tee<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        self.writer.flush()?;
+        Ok(n)
+    }
+}
+
+fn bencode_json(value: &bencode_rs::Value) -> serde_json::Value {
+    match value {
+        bencode_rs::Value::Str(s) => serde_json::Value::String(s.clone()),
+        bencode_rs::Value::List(items) => items.iter().map(bencode_json).collect(),
+        bencode_rs::Value::Map(map) => {
+            let mut object = serde_json::Map::new();
+            for (key, value) in map.iter() {
+                object.insert(bencode_text(key), bencode_json(value));
+            }
+            serde_json::Value::Object(object)
+        }
+        value => {
+            let text = value.to_string();
+            text.parse::<i64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| serde_json::Value::String(text))
+        }
+    }
+}
+
+struct ProxyLog {
+    json: bool,
+    ops: Vec<String>,
+    sessions: Vec<String>,
+    // ids of the requests that passed the op filter, their responses are
+    // logged too
+    ids: Mutex<BTreeSet<String>>,
+}
+
+impl ProxyLog {
+    fn log(&self, conn: usize, to_server: bool, msg: &bencode_rs::Value) {
+        let field = |key: &str| bencode_get(msg, key).map(bencode_text);
+
+        if !self.sessions.is_empty() {
+            match field("session") {
+                Some(session) if self.sessions.contains(&session) => {}
+                _ => return,
+            }
+        }
+        if !self.ops.is_empty() {
+            let mut ids = self.ids.lock().unwrap();
+            let id = format!("{}/{}", conn, field("id").unwrap_or_default());
+            if to_server {
+                match field("op") {
+                    Some(op) if self.ops.contains(&op) => ids.insert(id),
+                    _ => return,
+                };
+            } else if !ids.contains(&id) {
+                return;
+            } else if has_status(msg, "done") {
+                ids.remove(&id);
+            }
+        }
+
+        let now = chrono::Local::now();
+        let direction = if to_server { "->" } else { "<-" };
+        let message = bencode_json(msg);
+        let mut text = String::new();
+        if self.json {
+            let event = serde_json::json!({
+                "time": now.to_rfc3339(),
+                "conn": conn,
+                "direction": if to_server { "client->server" } else { "server->client" },
+                "message": message,
+            });
+            text.push_str(&format!("{}\n", event));
+        } else {
+            text.push_str(&format!(
+                "{} [{}] {}\n",
+                now.format("%H:%M:%S%.3f"),
+                conn,
+                direction
+            ));
+            if let serde_json::Value::Object(fields) = message {
+                for (key, value) in fields {
+                    text.push_str(&format!("  {}: {}\n", key, value));
+                }
+            }
+        }
+        // both directions log from threads of their own, a message is
+        // written in one go to keep it from being split by another
+        let _ = write_and_flush(&mut stdout().lock(), &text);
+    }
+}
+
+fn relay(
+    from: TcpStream,
+    to: TcpStream,
+    conn: usize,
+    to_server: bool,
+    log: Arc<ProxyLog>,
+) -> Result<()> {
+    let mut reader = BufReader::new(Tee {
+        reader: from,
+        writer: to,
+    });
+
+    loop {
+        match bencode_rs::parse_bencode(&mut reader) {
+            Ok(Some(msg)) => log.log(conn, to_server, &msg),
+            Ok(None) => break,
+            Err(e) => {
+                // the stream can't be followed anymore, keep relaying as is
+                eprintln!("[{}] unable to decode message: {}", conn, e);
+                std::io::copy(&mut reader, &mut std::io::sink())?;
+                break;
+            }
+        }
+    }
+
+    // let the other side know too
+    reader
+        .get_ref()
+        .writer
+        .shutdown(std::net::Shutdown::Write)?;
+    Ok(())
+}
+
+fn proxy_addr(addr: &str) -> String {
+    if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("127.0.0.1:{}", addr)
+    }
+}
+
+/// Relays nREPL traffic between clients connecting to `listen` and the
+/// server at `target`, logging each message.
+fn proxy(listen: &str, target: &str, log: ProxyLog) -> Result<()> {
+    let listener = std::net::TcpListener::bind(proxy_addr(listen))?;
+    let target = proxy_addr(target);
+    let log = Arc::new(log);
+    eprintln!(
+        "Relaying {} -> {}, stop with CTRL+C",
+        listener.local_addr()?,
+        target
+    );
+
+    for (conn, client) in listener.incoming().enumerate() {
+        let client = client?;
+        let server = match TcpStream::connect(&target) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("[{}] unable to connect to {}: {}", conn, target, e);
+                continue;
+            }
+        };
+        eprintln!("[{}] client {} connected", conn, client.peer_addr()?);
+
+        let (client_writer, server_writer) = (client.try_clone()?, server.try_clone()?);
+        let requests_log = log.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay(client, server_writer, conn, true, requests_log) {
+                eprintln!("[{}] client: {}", conn, e);
+            }
+        });
+        let responses_log = log.clone();
+        thread::spawn(move || {
+            if let Err(e) = relay(server, client_writer, conn, false, responses_log) {
+                eprintln!("[{}] server: {}", conn, e);
+            }
+            eprintln!("[{}] closed", conn);
+        });
     }
 
     Ok(())
 }
 
-fn get_repl(host: &str, port: usize) -> Result<Box<dyn Repl>> {
-    let mut stream = TcpStream::connect(format!("{}:{}", host, port))?;
-    let _ = stream.write_all(b"d4:code7:(+ 1 1)2:op4:evale\n")?;
-    stream.flush()?;
+/// nREPL server started by rclj for the project in the current directory,
+/// stopped when dropped.
+struct JackIn {
+    child: process::Child,
+    port: usize,
+}
+
+fn free_port() -> Result<usize> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port() as usize)
+}
+
+impl JackIn {
+    /// Starts the server, server output is sent line by line to `output`.
+    fn start(output: std::sync::mpsc::Sender<String>) -> Result<JackIn> {
+        let port = free_port()?;
+        let nrepl_port = PathBuf::from(".nrepl-port");
+        let port_arg = port.to_string();
+        let (project, program, args, port_file) = if Path::new("deps.edn").exists() {
+            let deps = r#"{:deps {nrepl/nrepl {:mvn/version "0.8.3"}}}"#;
+            let args = vec![
+                "-Sdeps",
+                deps,
+                "-M",
+                "-m",
+                "nrepl.cmdline",
+                "--port",
+                &port_arg,
+            ];
+            ("deps.edn", "clojure", args, Some(nrepl_port))
+        } else if Path::new("project.clj").exists() {
+            let args = vec!["repl", ":headless", ":port", &port_arg];
+            ("project.clj", "lein", args, Some(nrepl_port))
+        } else if Path::new("shadow-cljs.edn").exists() {
+            // shadow-cljs picks the port itself, see :nrepl in shadow-cljs.edn
+            let port_file = PathBuf::from(".shadow-cljs/nrepl.port");
+            let args = vec!["shadow-cljs", "server"];
+            ("shadow-cljs.edn", "npx", args, Some(port_file))
+        } else if Path::new("bb.edn").exists() {
+            ("bb.edn", "bb", vec!["nrepl-server", &port_arg], None)
+        } else {
+            bail!("No deps.edn, project.clj, shadow-cljs.edn or bb.edn found");
+        };
+
+        output.send(format!(
+            "Found {}, starting {} {}",
+            project,
+            program,
+            args.join(" ")
+        ))?;
+        let started = SystemTime::now();
+        let mut child = process::Command::new(program)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("Unable to start {}: {}", program, e))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .map(|out| Box::new(out) as Box<dyn Read + Send>);
+        let stderr = child
+            .stderr
+            .take()
+            .map(|err| Box::new(err) as Box<dyn Read + Send>);
+        for stream in vec![stdout, stderr].into_iter().flatten() {
+            let output = output.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines() {
+                    match line.map(|line| output.send(line)) {
+                        Ok(Ok(())) => {}
+                        _ => break,
+                    }
+                }
+            });
+        }
+
+        let mut server = JackIn { child, port };
+        server.port = server.wait_for_port(port_file.as_deref(), started)?;
+        Ok(server)
+    }
+
+    fn wait_for_port(&mut self, port_file: Option<&Path>, started: SystemTime) -> Result<usize> {
+        let timeout = Duration::from_millis(JACK_IN_TIMEOUT_MS);
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                bail!("Server exited with {}", status);
+            }
+            if started.elapsed().unwrap_or_default() > timeout {
+                bail!("Server didn't start in {} s", timeout.as_secs());
+            }
+
+            match port_file {
+                Some(path) => {
+                    let fresh = fs::metadata(path)
+                        .and_then(|meta| meta.modified())
+                        .map(|modified| modified >= started)
+                        .unwrap_or(false);
+                    let port = fs::read_to_string(path)
+                        .ok()
+                        .and_then(|port| port.trim().parse().ok());
+                    if let (true, Some(port)) = (fresh, port) {
+                        return Ok(port);
+                    }
+                }
+                None => {
+                    if TcpStream::connect(("127.0.0.1", self.port as u16)).is_ok() {
+                        return Ok(self.port);
+                    }
+                }
+            }
+
+            thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+        }
+    }
+}
+
+impl Drop for JackIn {
+    fn drop(&mut self) {
+        // lein and shadow-cljs run the server in a process of their own that
+        // killing the child would leave running, so the server is asked to
+        // exit first
+        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], self.port as u16));
+        let timeout = Duration::from_millis(WATCH_INTERVAL_MS);
+        if let Ok(mut stream) = TcpStream::connect_timeout(&addr, timeout) {
+            let _ = stream.write_all(b"d4:code15:(System/exit 0)2:op4:evale");
+            for _ in 0..10 {
+                if let Ok(Some(_)) = self.child.try_wait() {
+                    return;
+                }
+                thread::sleep(timeout);
+            }
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn get_repl(endpoint: &Endpoint) -> Result<Box<dyn Repl>> {
+    let mut stream = endpoint.connect()?;
+    let _ = stream
+        .write_all(b"d4:code7:(+ 1 1)2:op4:evale\n")
+        .map_err(connection_lost)?;
+    stream.flush().map_err(connection_lost)?;
 
     let mut buf = [0u8; 1];
-    stream.read_exact(&mut buf)?;
+    stream.read_exact(&mut buf).map_err(connection_lost)?;
 
     // restart connection from clean state
-    stream.shutdown(std::net::Shutdown::Both)?;
-    stream = TcpStream::connect(format!("{}:{}", host, port))?;
+    drop(stream);
+    stream = endpoint.connect()?;
 
     match buf[0] {
         123 => {
@@ -332,31 +3575,496 @@ fn get_repl(host: &str, port: usize) -> Result<Box<dyn Repl>> {
     }
 }
 
+#[derive(StructOpt, Debug)]
+enum SubCommand {
+    /// Relay nREPL traffic between a client and a server, logging each message
+    Proxy {
+        /// Port, or host:port, to accept clients on
+        #[structopt(long)]
+        listen: String,
+
+        /// Port, or host:port, of the nREPL server
+        #[structopt(long)]
+        target: String,
+
+        /// Log messages as JSON lines
+        #[structopt(long)]
+        json: bool,
+
+        /// Only log requests with this op and their responses
+        #[structopt(long)]
+        op: Vec<String>,
+
+        /// Only log messages of this session
+        #[structopt(long)]
+        session: Vec<String>,
+    },
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "rclj")]
 struct Opt {
-    /// Repl host
-    #[structopt(short, default_value = "127.0.0.1")]
-    host: String,
+    /// Repl host [default: 127.0.0.1]
+    #[structopt(short)]
+    host: Option<String>,
 
     /// Repl port
     #[structopt(short)]
-    port: usize,
+    port: Option<usize>,
+
+    /// Connection profile from the config file
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// Read forms or JSON requests from stdin and write responses as JSON lines
+    #[structopt(long)]
+    json: bool,
+
+    /// Log every form and response with timestamps to file
+    #[structopt(long, parse(from_os_str))]
+    transcript: Option<PathBuf>,
+
+    /// Full-screen mode with separate panes for results and output
+    #[structopt(long)]
+    tui: bool,
+
+    /// Cut the output of an evaluation after this many characters, see :more
+    #[structopt(long)]
+    max_chars: Option<usize>,
+
+    /// Print at most this many items of a collection (*print-length*)
+    #[structopt(long)]
+    print_length: Option<usize>,
+
+    /// Print at most this many levels of nested collections (*print-level*)
+    #[structopt(long)]
+    print_level: Option<usize>,
+
+    /// Show how long each evaluation took, toggle with :timing
+    #[structopt(long)]
+    timing: bool,
+
+    /// Reload changed .clj/.cljc files under the given paths until interrupted
+    #[structopt(long, parse(from_os_str))]
+    watch: Vec<PathBuf>,
+
+    /// Run the tests of the given namespaces, or the current one, and exit
+    /// non-zero on failures
+    #[structopt(long)]
+    test: Option<Vec<String>>,
+
+    /// Evaluate forms from file, e.g. one written with :save, and exit
+    #[structopt(long, parse(from_os_str))]
+    script: Option<PathBuf>,
+
+    /// Connect to a REPL listening on this Unix domain socket instead of a port
+    #[structopt(long, parse(from_os_str))]
+    socket: Option<PathBuf>,
+
+    /// Connect with TLS
+    #[structopt(long)]
+    tls: bool,
+
+    /// CA certificate (PEM) to verify the server with, see --tls
+    #[structopt(long, parse(from_os_str))]
+    tls_ca: Option<PathBuf>,
+
+    /// Client certificate (PEM), see --tls
+    #[structopt(long, parse(from_os_str))]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM, PKCS #8) of the client certificate, see --tls
+    #[structopt(long, parse(from_os_str))]
+    tls_key: Option<PathBuf>,
+
+    /// Evaluate in this namespace with --script and --json
+    #[structopt(long)]
+    ns: Option<String>,
+
+    /// Start an nREPL server for the project in the current directory and
+    /// connect to it, the server is stopped on exit
+    #[structopt(long)]
+    jack_in: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<SubCommand>,
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
-    let repl = get_repl(&opt.host, opt.port)?;
+    if let Some(SubCommand::Proxy {
+        listen,
+        target,
+        json,
+        op,
+        session,
+    }) = opt.cmd
+    {
+        let log = ProxyLog {
+            json,
+            ops: op,
+            sessions: session,
+            ids: Mutex::new(BTreeSet::new()),
+        };
+        return proxy(&listen, &target, log);
+    }
+    let config = Config::load()?;
 
-    println!(
-        "\nConnected to {} at {}:{}",
-        repl.repl_type(),
-        &opt.host,
-        opt.port
-    );
+    let profile = match opt.profile.as_ref().or(config.default_profile.as_ref()) {
+        Some(name) => config
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown profile '{}'", name))?,
+        None => Profile::default(),
+    };
+    let (server_tx, server_rx) = channel();
+    let server = if opt.jack_in {
+        Some(JackIn::start(server_tx)?)
+    } else {
+        None
+    };
+    let host = match &server {
+        Some(_) => "127.0.0.1".into(),
+        None => opt
+            .host
+            .clone()
+            .or(profile.host)
+            .unwrap_or_else(|| "127.0.0.1".into()),
+    };
+    let port = match &server {
+        Some(server) => server.port,
+        None if opt.socket.is_some() => 0,
+        None => opt
+            .port
+            .or(profile.port)
+            .ok_or_else(|| anyhow!("No port given, use -p, --socket, a profile or --jack-in"))?,
+    };
+    let tls = if opt.tls {
+        Some(Tls {
+            ca: opt.tls_ca.clone(),
+            cert: opt.tls_cert.clone(),
+            key: opt.tls_key.clone(),
+        })
+    } else {
+        None
+    };
+    let endpoint = Endpoint {
+        host,
+        port,
+        socket: opt.socket.clone(),
+        tls,
+    };
+    let init: Vec<String> = config
+        .init
+        .iter()
+        .chain(profile.init.iter())
+        .cloned()
+        .collect();
+
+    if opt.tui {
+        let output = server.as_ref().map(|_| server_rx);
+        return tui(&endpoint, &init, output);
+    }
+    if server.is_some() {
+        thread::spawn(move || {
+            for line in server_rx {
+                eprintln!("{}", line);
+            }
+        });
+    }
+
+    let mut repl = get_repl(&endpoint)?;
+    let printing = Printing {
+        timing: opt.timing,
+        max_chars: opt.max_chars,
+        print_length: opt.print_length,
+        print_level: opt.print_level,
+        truncated: None,
+        colors: config.colors.clone(),
+    };
+    if let Err(e) = apply_print_limits(&mut *repl, &printing) {
+        eprintln!("Unable to set print limits: {}", e);
+    }
+    run_init_forms(&mut *repl, &init)?;
+    let mut transcript = Transcript::open(opt.transcript.as_deref())?;
+
+    if let Some(path) = &opt.script {
+        return script(repl, opt.ns.as_deref(), path, &mut transcript);
+    }
+    if opt.json {
+        return json_loop(repl, opt.ns.as_deref(), &endpoint, &mut transcript);
+    }
+    if let Some(namespaces) = &opt.test {
+        let report = run_tests(&mut *repl, namespaces)?;
+        report.print(&printing.colors);
+        repl.quit()?;
+        if report.failed() {
+            drop(server);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if !opt.watch.is_empty() {
+        println!("Watching for changes, stop with CTRL+C");
+        let mut log = |msg: &str| println!("{}", msg);
+        return watch(
+            &mut *repl,
+            opt.watch.clone(),
+            &AtomicBool::new(false),
+            &mut log,
+        );
+    }
+
+    println!("\nConnected to {} at {}", repl.repl_type(), endpoint);
+    println!("Server info: :describe, save evaluated forms: :save file.clj");
+    println!("Timing: :timing, benchmark a form: :bench n form");
+    println!("Browse the last result: :inspect, or a form: :inspect form");
+    println!("Page truncated output: :more");
+    println!("Reload files on change: :watch path, stop: :unwatch");
+    println!("Run tests: :test, or :test ns...");
+    println!("Evaluate in a namespace: :in ns form, list namespaces: :ns [prefix]");
+    println!("ClojureScript: :cljs build-id, back to Clojure: :clj");
+    if !config.commands.is_empty() {
+        let names: Vec<String> = config.commands.keys().map(|k| format!(":{}", k)).collect();
+        println!("Custom commands: {}", names.join(" "));
+    }
     println!("Exit: CTRL+D\n");
 
-    main_loop(repl)?;
+    main_loop(repl, &endpoint, &mut transcript, printing, &config, &init)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `|` marks the cursor, before and after the edit
+    fn edit(edit: Paredit, text: &str) -> Option<String> {
+        let pos = text.find('|').unwrap();
+        let line = text.replacen('|', "", 1);
+        paredit(edit, &line, pos).map(|(line, pos)| format!("{}|{}", &line[..pos], &line[pos..]))
+    }
+
+    fn cmd(edit: Paredit, text: &str, emacs: bool) -> Option<Cmd> {
+        let pos = text.find('|').unwrap();
+        let line = text.replacen('|', "", 1);
+        let (new_line, new_pos) = paredit(edit, &line, pos)?;
+        command(&line, pos, &new_line, new_pos, emacs)
+    }
+
+    #[test]
+    fn scan() {
+        let line = r#"(a [b] "c)" \) {d)"#;
+        let scan = Scan::new(line);
+        assert_eq!(scan.matches.get(&3), Some(&5));
+        assert_eq!(scan.matches.get(&5), Some(&3));
+        assert_eq!(scan.matches.get(&0), None);
+        assert_eq!(scan.matches.get(&15), None);
+        assert_eq!(scan.strings, vec![(7, 10)]);
+        assert_eq!(scan.string_at(7), None);
+        assert_eq!(scan.string_at(8), Some((7, 10)));
+        assert_eq!(scan.string_at(10), Some((7, 10)));
+
+        assert_eq!(Scan::new(r#"a "b"#).strings, vec![(2, 4)]);
+        assert_eq!(Scan::new("; (a\n(b)").matches.get(&5), Some(&7));
+    }
+
+    #[test]
+    fn scan_forms() {
+        let line = "(a [b c] #{d} 'e) \"f\"";
+        let scan = Scan::new(line);
+        assert_eq!(scan.enclosing(line, 5), Some((3, 7)));
+        assert_eq!(scan.enclosing(line, 9), Some((0, 16)));
+        assert_eq!(scan.enclosing(line, 17), None);
+        assert_eq!(
+            scan.forms(line, 1, 16),
+            vec![(1, 2), (3, 8), (9, 13), (14, 16)]
+        );
+        assert_eq!(scan.forms(line, 0, line.len()), vec![(0, 17), (18, 21)]);
+        for (line, end) in &[("#_a b", Some(3)), ("(a", None), (r#""a"#, None)] {
+            assert_eq!(Scan::new(line).form_end(line, 0), *end);
+        }
+    }
+
+    #[test]
+    fn insert() {
+        assert_eq!(edit(Paredit::Open(b'('), "(a |b)"), Some("(a (|)b)".into()));
+        assert_eq!(edit(Paredit::Open(b'['), "|"), Some("[|]".into()));
+        assert_eq!(edit(Paredit::Open(b'('), r#""a|""#), None);
+        assert_eq!(edit(Paredit::Open(b'('), r"\|"), None);
+
+        assert_eq!(edit(Paredit::Close(b')'), "(a|)"), Some("(a)|".into()));
+        assert_eq!(edit(Paredit::Close(b')'), "(a| b)"), None);
+        assert_eq!(edit(Paredit::Close(b']'), "(a|)"), None);
+
+        assert_eq!(edit(Paredit::Quote, "(a |)"), Some(r#"(a "|")"#.into()));
+        assert_eq!(edit(Paredit::Quote, r#""a|b""#), Some(r#""a\"|b""#.into()));
+        assert_eq!(edit(Paredit::Quote, r#""ab|""#), Some(r#""ab"|"#.into()));
+        assert_eq!(edit(Paredit::Quote, r#""ab|"#), None);
+        assert_eq!(edit(Paredit::Quote, r"\|"), None);
+        assert_eq!(edit(Paredit::Quote, r#""a\|b""#), None);
+    }
+
+    #[test]
+    fn delete() {
+        assert_eq!(edit(Paredit::Backspace, "(|)"), Some("(|".into()));
+        assert_eq!(edit(Paredit::Backspace, "(|"), None);
+        assert_eq!(edit(Paredit::Backspace, "(a)|"), Some("(a|)".into()));
+        assert_eq!(edit(Paredit::Backspace, "(|a)"), Some("|(a)".into()));
+        assert_eq!(edit(Paredit::Backspace, r#"a "|""#), Some(r#"a "|"#.into()));
+        assert_eq!(edit(Paredit::Backspace, r#"a "|"#), None);
+        assert_eq!(edit(Paredit::Backspace, r#""|a"#), None);
+        assert_eq!(edit(Paredit::Backspace, r#""|a""#), Some(r#"|"a""#.into()));
+        assert_eq!(edit(Paredit::Backspace, r#""a"|"#), Some(r#""a|""#.into()));
+        assert_eq!(edit(Paredit::Backspace, r#""a|""#), None);
+        assert_eq!(edit(Paredit::Backspace, "(a|)"), None);
+        assert_eq!(edit(Paredit::Backspace, "|"), None);
+
+        assert_eq!(edit(Paredit::Delete, "|()"), Some("|".into()));
+        assert_eq!(edit(Paredit::Delete, "|(a)"), Some("(|a)".into()));
+        assert_eq!(edit(Paredit::Delete, "(a|)"), Some("(a)|".into()));
+        assert_eq!(edit(Paredit::Delete, r#"|"""#), Some("|".into()));
+        assert_eq!(edit(Paredit::Delete, r#""|""#), Some(r#"""|"#.into()));
+        assert_eq!(edit(Paredit::Delete, r#"|""#), None);
+        assert_eq!(edit(Paredit::Delete, r#""a|""#), Some(r#""a"|"#.into()));
+        assert_eq!(edit(Paredit::Delete, r#"|"a""#), Some(r#""|a""#.into()));
+        assert_eq!(edit(Paredit::Delete, "(|a)"), None);
+        assert_eq!(edit(Paredit::Delete, "a|"), None);
+    }
+
+    #[test]
+    fn kill() {
+        assert_eq!(
+            edit(Paredit::KillForms, "(a |b c) d"),
+            Some("(a |) d".into())
+        );
+        assert_eq!(edit(Paredit::KillForms, "a |b (c d)"), Some("a |".into()));
+        assert_eq!(
+            edit(Paredit::KillForms, r#"("a|bc" d)"#),
+            Some(r#"("a|" d)"#.into())
+        );
+        assert_eq!(edit(Paredit::KillForm, "(a |b c)"), Some("(a | c)".into()));
+        assert_eq!(
+            edit(Paredit::KillForm, "(a |[b c] d)"),
+            Some("(a | d)".into())
+        );
+        assert_eq!(edit(Paredit::KillForm, "(a |)"), None);
+        assert_eq!(edit(Paredit::KillForm, r#""a|b""#), None);
+    }
+
+    #[test]
+    fn slurp_and_barf() {
+        assert_eq!(edit(Paredit::Slurp, "(a|) b c"), Some("(a| b) c".into()));
+        assert_eq!(
+            edit(Paredit::Slurp, "[(a|) (b)]"),
+            Some("[(a| (b))]".into())
+        );
+        assert_eq!(edit(Paredit::Slurp, "(a|)"), None);
+        assert_eq!(edit(Paredit::Barf, "(a| b c)"), Some("(a| b) c".into()));
+        assert_eq!(edit(Paredit::Barf, "(a b c|)"), Some("(a b) c|".into()));
+        assert_eq!(edit(Paredit::Barf, "(|)"), None);
+    }
+
+    #[test]
+    fn commands() {
+        // the keys bound in vi mode only
+        let insert = |s: &str| Some(Cmd::Insert(1, s.into()));
+        assert_eq!(cmd(Paredit::Open(b'('), "(a |b)", false), insert("()"));
+        assert_eq!(cmd(Paredit::Quote, "(a |b)", false), insert(r#""""#));
+        assert_eq!(cmd(Paredit::Quote, r#""a|b""#, false), None);
+        assert_eq!(
+            cmd(Paredit::Close(b')'), "(a|)", false),
+            Some(Cmd::Move(Movement::ForwardChar(1)))
+        );
+        assert_eq!(
+            cmd(Paredit::Backspace, "(a)|", false),
+            Some(Cmd::Move(Movement::BackwardChar(1)))
+        );
+        assert_eq!(
+            cmd(Paredit::Backspace, "(|)", false),
+            Some(Cmd::Kill(Movement::ForwardChar(1)))
+        );
+        assert_eq!(
+            cmd(Paredit::Delete, "|()", false),
+            Some(Cmd::Kill(Movement::ForwardChar(2)))
+        );
+
+        // and the ones bound in both modes
+        assert_eq!(
+            cmd(Paredit::KillForms, "(a |b c) d", true),
+            Some(Cmd::Kill(Movement::ForwardChar(3)))
+        );
+        assert_eq!(
+            cmd(Paredit::Slurp, "(a|) b c", true),
+            Some(Cmd::Replace(Movement::EndOfBuffer, Some(" b) c".into())))
+        );
+        assert_eq!(
+            cmd(Paredit::Barf, "(a| b c)", true),
+            Some(Cmd::Replace(Movement::EndOfBuffer, Some(" b) c".into())))
+        );
+        assert_eq!(cmd(Paredit::Barf, "(a b c|)", true), None);
+
+        assert_eq!(
+            command("abc", 2, "ac", 1, true),
+            Some(Cmd::Kill(Movement::BackwardChar(1)))
+        );
+        assert_eq!(
+            command("abcd", 3, "aXd", 1, true),
+            Some(Cmd::Replace(Movement::BackwardChar(2), Some("X".into())))
+        );
+    }
+
+    #[test]
+    fn namespace_loading_forms() {
+        assert!(loads_namespaces("(ns foo.bar)"));
+        assert!(loads_namespaces("(inc 1) (require '[foo.bar])"));
+        assert!(loads_namespaces("(load-file \"src/foo.clj\")"));
+        assert!(!loads_namespaces("(download \"x\")"));
+        assert!(!loads_namespaces("(println \"(require 'x)\")"));
+        assert!(!loads_namespaces("(nsa 1)"));
+    }
+
+    #[test]
+    fn forms() {
+        assert_eq!(
+            split_forms(r#"(a b) c "d e""#),
+            vec!["(a b)", "c", r#""d e""#]
+        );
+        assert_eq!(
+            split_forms(r#"#inst "2020-01-01" x"#),
+            vec![r#"#inst "2020-01-01""#, "x"]
+        );
+        assert_eq!(
+            split_forms("^:private (def a 1) ^{:b 2} [c]"),
+            vec!["^:private (def a 1)", "^{:b 2} [c]"]
+        );
+        assert_eq!(split_forms("#_(a) #_ b c #_d"), vec!["c"]);
+        assert_eq!(split_forms("a ; b (c\n(d ; e)\n)"), vec!["a", "(d \n)"]);
+        assert_eq!(split_forms("#{a} 'b #(c %)"), vec!["#{a}", "'b", "#(c %)"]);
+    }
+
+    #[test]
+    fn custom_commands() {
+        let mut config = Config::default();
+        config
+            .commands
+            .insert("doc".into(), "(clojure.repl/doc %1)".into());
+        config.commands.insert("all".into(), "[%&] %2".into());
+        assert_eq!(
+            config.expand_command(":doc map"),
+            Some("(clojure.repl/doc map)".into())
+        );
+        assert_eq!(
+            config.expand_command(r#":all (a b) #inst "2020""#),
+            Some(r#"[(a b) #inst "2020"] #inst "2020""#.into())
+        );
+        assert_eq!(
+            config.expand_command(":doc"),
+            Some("(clojure.repl/doc )".into())
+        );
+        assert_eq!(config.expand_command(":unknown x"), None);
+        assert_eq!(config.expand_command("(doc x)"), None);
+    }
+}
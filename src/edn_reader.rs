@@ -0,0 +1,734 @@
+//! A validating EDN reader that keeps the source as written.
+//!
+//! `Reader` reads EDN text into `Form`s that keep comments, discarded forms
+//! and the text of scalars along with their line and column, reporting
+//! invalid input as a `SyntaxError` with the position of the problem.
+
+use chrono::DateTime;
+use std::collections::HashSet;
+use std::fmt;
+
+const SYMBOL_CHARS: &str = ".*+!-_?$%&=<>/:#'";
+const MAX_DEPTH: usize = 1000;
+const CHAR_NAMES: [&str; 6] = ["newline", "return", "space", "tab", "formfeed", "backspace"];
+
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coll {
+    List,
+    Vector,
+    Map,
+    Set,
+}
+
+impl Coll {
+    pub fn open(&self) -> &'static str {
+        match self {
+            Coll::List => "(",
+            Coll::Vector => "[",
+            Coll::Map => "{",
+            Coll::Set => "#{",
+        }
+    }
+
+    pub fn close(&self) -> char {
+        match self {
+            Coll::List => ')',
+            Coll::Vector => ']',
+            Coll::Map | Coll::Set => '}',
+        }
+    }
+}
+
+/// EDN as written, including comments and discarded forms. Scalars keep
+/// their source text. The forms of tags and discards end with the operand,
+/// preceded by any comments and discards between it and the `#`.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Atom(String),
+    Coll(Coll, Vec<Form>),
+    Tagged(String, Vec<Form>),
+    NsMap(String, Vec<Form>),
+    Discard(Vec<Form>),
+    Comment(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Form {
+    pub node: Node,
+    pub line: usize,
+    pub col: usize,
+    /// Line breaks between the previous form and this one.
+    pub newlines: usize,
+}
+
+impl Form {
+    /// Comments and discarded forms aren't part of the value.
+    pub fn is_value(&self) -> bool {
+        !matches!(self.node, Node::Comment(_) | Node::Discard(_))
+    }
+
+    /// Single line text of the form without comments, for comparing values.
+    pub fn flat(&self) -> String {
+        let items = |items: &[Form]| {
+            items
+                .iter()
+                .filter(|item| item.is_value())
+                .map(Form::flat)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        match &self.node {
+            Node::Atom(text) => text.clone(),
+            Node::Coll(coll, forms) => format!("{}{}{}", coll.open(), items(forms), coll.close()),
+            Node::Tagged(tag, forms) => format!("#{} {}", tag, items(forms)),
+            Node::NsMap(ns, forms) => format!("#:{}{{{}}}", ns, items(forms)),
+            Node::Discard(forms) => format!("#_{}", items(forms)),
+            Node::Comment(text) => text.clone(),
+        }
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()[]{}\",;\\".contains(c)
+}
+
+pub fn is_number(token: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let unsigned = token.trim_start_matches(&['+', '-'][..]);
+    if token.len() - unsigned.len() > 1 {
+        return false;
+    }
+    if let Some(int) = unsigned.strip_suffix('N') {
+        return digits(int) && (int == "0" || !int.starts_with('0'));
+    }
+    let float = unsigned.strip_suffix('M').unwrap_or(unsigned);
+    let (mantissa, exponent) = match float.find(&['e', 'E'][..]) {
+        Some(i) => (&float[..i], Some(&float[i + 1..])),
+        None => (float, None),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], Some(&mantissa[i + 1..])),
+        None => (mantissa, None),
+    };
+    let exponent = exponent.map(|exp| exp.strip_prefix(&['+', '-'][..]).unwrap_or(exp));
+    digits(int)
+        && (int == "0" || !int.starts_with('0'))
+        && frac.into_iter().all(|frac| frac.is_empty() || digits(frac))
+        && exponent.into_iter().all(digits)
+}
+
+pub fn is_keyword_name(name: &str) -> bool {
+    is_symbol(name) && !name.starts_with('/')
+}
+
+pub fn is_symbol(token: &str) -> bool {
+    let valid_part = |part: &str| {
+        let mut chars = part.chars();
+        match chars.next() {
+            None => false,
+            Some(c) if c.is_ascii_digit() || c == ':' || c == '#' => false,
+            Some(c) if c == '+' || c == '-' || c == '.' => {
+                !matches!(chars.next(), Some(c) if c.is_ascii_digit())
+            }
+            Some(_) => true,
+        }
+    };
+    if !token
+        .chars()
+        .all(|c| c.is_alphanumeric() || SYMBOL_CHARS.contains(c))
+    {
+        return false;
+    }
+    match token.find('/') {
+        _ if token == "/" => true,
+        Some(i) => {
+            valid_part(&token[..i]) && (&token[i + 1..] == "/" || valid_part(&token[i + 1..]))
+        }
+        None => valid_part(token),
+    }
+}
+
+pub struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    depth: usize,
+}
+
+impl Reader {
+    pub fn new(src: &str) -> Reader {
+        Reader {
+            chars: src.chars().collect(),
+            pos: 0,
+            line: 1,
+            col: 1,
+            depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    /// Keeps deeply nested input from overflowing the stack.
+    fn nest(&mut self, line: usize, col: usize) -> Result<(), SyntaxError> {
+        if self.depth == MAX_DEPTH {
+            return self.error(line, col, "Nested too deeply".into());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn error<T>(&self, line: usize, col: usize, message: String) -> Result<T, SyntaxError> {
+        Err(SyntaxError { line, col, message })
+    }
+
+    /// Skips whitespace and commas returning the number of line breaks.
+    fn space(&mut self) -> usize {
+        let mut newlines = 0;
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                newlines += 1;
+            } else if !c.is_whitespace() && c != ',' {
+                break;
+            }
+            self.next();
+        }
+        newlines
+    }
+
+    fn token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.peek() {
+            if is_delimiter(c) {
+                break;
+            }
+            token.push(c);
+            self.next();
+        }
+        token
+    }
+
+    /// Reads the next form, or None at the end of input or of a collection.
+    fn read(&mut self) -> Result<Option<Form>, SyntaxError> {
+        let newlines = self.space();
+        let (line, col) = (self.line, self.col);
+        let node = match self.peek() {
+            None | Some(')') | Some(']') | Some('}') => return Ok(None),
+            Some(';') => {
+                let mut text = String::new();
+                while let Some(c) = self.peek().filter(|c| *c != '\n') {
+                    text.push(c);
+                    self.next();
+                }
+                Node::Comment(text.trim_end().to_string())
+            }
+            Some('(') => {
+                self.next();
+                Node::Coll(Coll::List, self.items(Coll::List, line, col)?)
+            }
+            Some('[') => {
+                self.next();
+                Node::Coll(Coll::Vector, self.items(Coll::Vector, line, col)?)
+            }
+            Some('{') => {
+                self.next();
+                Node::Coll(Coll::Map, self.items(Coll::Map, line, col)?)
+            }
+            Some('"') => Node::Atom(self.string(line, col)?),
+            Some('\\') => Node::Atom(self.character(line, col)?),
+            Some('#') => self.dispatch(line, col)?,
+            Some(_) => {
+                let token = self.token();
+                match token.strip_prefix(':') {
+                    Some(name) if name.starts_with(':') => {
+                        return self.error(
+                            line,
+                            col,
+                            format!("Auto-resolved keyword {} isn't EDN", token),
+                        )
+                    }
+                    Some(name) if !is_keyword_name(name) => {
+                        return self.error(line, col, format!("Invalid keyword {}", token))
+                    }
+                    None if !is_number(&token) && !is_symbol(&token) => {
+                        let what = if token.starts_with(|c: char| c.is_ascii_digit()) {
+                            "number"
+                        } else {
+                            "symbol"
+                        };
+                        return self.error(line, col, format!("Invalid {} {}", what, token));
+                    }
+                    _ => Node::Atom(token),
+                }
+            }
+        };
+        Ok(Some(Form {
+            node,
+            line,
+            col,
+            newlines,
+        }))
+    }
+
+    /// Reads up to the next form that is a value, for tags and discards.
+    fn operand(&mut self, what: &str, line: usize, col: usize) -> Result<Vec<Form>, SyntaxError> {
+        let mut forms = vec![];
+        self.nest(line, col)?;
+        loop {
+            match self.read()? {
+                Some(form) if form.is_value() => {
+                    forms.push(form);
+                    self.depth -= 1;
+                    return Ok(forms);
+                }
+                Some(form) => forms.push(form),
+                None => return self.error(line, col, format!("{} without a form", what)),
+            }
+        }
+    }
+
+    fn items(&mut self, coll: Coll, line: usize, col: usize) -> Result<Vec<Form>, SyntaxError> {
+        let mut items = vec![];
+        self.nest(line, col)?;
+        while let Some(form) = self.read()? {
+            items.push(form);
+        }
+        self.depth -= 1;
+        match self.peek() {
+            Some(c) if c == coll.close() => {
+                self.next();
+            }
+            Some(c) => {
+                return self.error(
+                    self.line,
+                    self.col,
+                    format!(
+                        "Unexpected {}, expected {} to close {} from {}:{}",
+                        c,
+                        coll.close(),
+                        coll.open(),
+                        line,
+                        col
+                    ),
+                )
+            }
+            None => return self.error(line, col, format!("Unclosed {}", coll.open())),
+        }
+
+        let values: Vec<&Form> = items.iter().filter(|item| item.is_value()).collect();
+        let keys: Vec<&Form> = match coll {
+            Coll::Map if values.len() % 2 == 1 => {
+                return self.error(line, col, "Map with an odd number of forms".into())
+            }
+            Coll::Map => values.into_iter().step_by(2).collect(),
+            Coll::Set => values,
+            _ => vec![],
+        };
+        let mut seen = HashSet::new();
+        for key in keys {
+            let flat = key.flat();
+            if !seen.insert(flat.clone()) {
+                let what = if coll == Coll::Set {
+                    "set element"
+                } else {
+                    "key"
+                };
+                return self.error(key.line, key.col, format!("Duplicate {} {}", what, flat));
+            }
+        }
+        Ok(items)
+    }
+
+    fn string(&mut self, line: usize, col: usize) -> Result<String, SyntaxError> {
+        let mut text = String::new();
+        text.push(self.next().unwrap());
+        loop {
+            let (esc_line, esc_col) = (self.line, self.col);
+            match self.next() {
+                None => return self.error(line, col, "Unterminated string".into()),
+                Some('"') => {
+                    text.push('"');
+                    return Ok(text);
+                }
+                Some('\\') => {
+                    text.push('\\');
+                    match self.next() {
+                        Some(c) if "tnr\\\"bf".contains(c) => text.push(c),
+                        Some('u') => {
+                            text.push('u');
+                            for _ in 0..4 {
+                                match self.next().filter(|c| c.is_ascii_hexdigit()) {
+                                    Some(c) => text.push(c),
+                                    None => {
+                                        return self.error(
+                                            esc_line,
+                                            esc_col,
+                                            "Invalid \\u escape in string".into(),
+                                        )
+                                    }
+                                }
+                            }
+                        }
+                        None => return self.error(line, col, "Unterminated string".into()),
+                        Some(c) => {
+                            return self.error(
+                                esc_line,
+                                esc_col,
+                                format!("Invalid escape \\{} in string", c),
+                            )
+                        }
+                    }
+                }
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn character(&mut self, line: usize, col: usize) -> Result<String, SyntaxError> {
+        self.next();
+        let first = match self.next() {
+            Some(c) if !c.is_whitespace() => c,
+            _ => return self.error(line, col, "Backslash without a character".into()),
+        };
+        let name = format!("{}{}", first, self.token());
+        let valid = name.chars().count() == 1
+            || CHAR_NAMES.contains(&name.as_str())
+            || (name.len() == 5
+                && name.starts_with('u')
+                && name[1..].chars().all(|c| c.is_ascii_hexdigit()));
+        if !valid {
+            return self.error(line, col, format!("Invalid character \\{}", name));
+        }
+        Ok(format!("\\{}", name))
+    }
+
+    fn dispatch(&mut self, line: usize, col: usize) -> Result<Node, SyntaxError> {
+        self.next();
+        match self.peek() {
+            Some('{') => {
+                self.next();
+                Ok(Node::Coll(Coll::Set, self.items(Coll::Set, line, col)?))
+            }
+            Some('_') => {
+                self.next();
+                Ok(Node::Discard(self.operand("#_", line, col)?))
+            }
+            Some(':') => {
+                self.next();
+                let ns = self.token();
+                if !is_symbol(&ns) || ns.contains('/') {
+                    return self.error(line, col, format!("Invalid namespace #:{}", ns));
+                }
+                if self.peek() != Some('{') {
+                    return self.error(line, col, format!("Expected a map after #:{}", ns));
+                }
+                self.next();
+                Ok(Node::NsMap(ns, self.items(Coll::Map, line, col)?))
+            }
+            Some('#') => {
+                self.next();
+                match self.token().as_str() {
+                    value @ "Inf" | value @ "-Inf" | value @ "NaN" => {
+                        Ok(Node::Atom(format!("##{}", value)))
+                    }
+                    value => self.error(line, col, format!("Invalid symbolic value ##{}", value)),
+                }
+            }
+            Some(c) if c.is_alphabetic() => {
+                let tag = self.token();
+                if !is_symbol(&tag) {
+                    return self.error(line, col, format!("Invalid tag #{}", tag));
+                }
+                let forms = self.operand(&format!("#{}", tag), line, col)?;
+                Ok(Node::Tagged(tag, forms))
+            }
+            Some(c) => self.error(line, col, format!("Invalid dispatch #{}", c)),
+            None => self.error(line, col, "Unexpected end of input after #".into()),
+        }
+    }
+
+    /// Reads all top-level forms of the input.
+    pub fn read_all(&mut self) -> Result<Vec<Form>, SyntaxError> {
+        let mut forms = vec![];
+        loop {
+            match self.read()? {
+                Some(form) => forms.push(form),
+                None => match self.peek() {
+                    None => return Ok(forms),
+                    Some(c) => return self.error(self.line, self.col, format!("Unexpected {}", c)),
+                },
+            }
+        }
+    }
+}
+
+/// The value of a string atom, `text` including the quotes.
+pub fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut units = vec![];
+    let mut chars = text[1..text.len() - 1].chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.as_str().starts_with('u') {
+            chars.next();
+            let hex: String = chars.by_ref().take(4).collect();
+            units.push(u16::from_str_radix(&hex, 16).unwrap_or(0xfffd));
+            continue;
+        }
+        out.extend(std::char::decode_utf16(units.drain(..)).map(|c| c.unwrap_or('\u{fffd}')));
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('b') => out.push('\u{8}'),
+                Some('f') => out.push('\u{c}'),
+                Some(c) => out.push(c),
+                None => {}
+            },
+            c => out.push(c),
+        }
+    }
+    out.extend(std::char::decode_utf16(units).map(|c| c.unwrap_or('\u{fffd}')));
+    out
+}
+
+/// The string atom for `s`, the inverse of `unescape`.
+pub fn escape(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The value of a number atom, `Big` for integers that don't fit 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Big,
+}
+
+/// Parses a number atom, None when it isn't a valid one. The N and M
+/// suffixes are dropped, M making it a float.
+pub fn number(text: &str) -> Option<Number> {
+    let digits = text.trim_end_matches(&['N', 'M'][..]);
+    if !text.ends_with('M') && !digits.contains(&['.', 'e', 'E'][..]) {
+        if let Ok(n) = digits.parse::<i64>() {
+            return Some(Number::Int(n));
+        }
+        if let Ok(n) = digits.parse::<u64>() {
+            return Some(Number::UInt(n));
+        }
+        return Some(Number::Big);
+    }
+    digits.parse().ok().map(Number::Float)
+}
+
+/// The value of a character atom like `\\newline`.
+pub fn character(text: &str) -> char {
+    match &text[1..] {
+        "newline" => '\n',
+        "return" => '\r',
+        "space" => ' ',
+        "tab" => '\t',
+        "formfeed" => '\u{c}',
+        "backspace" => '\u{8}',
+        name if name.len() == 5 && name.starts_with('u') => u32::from_str_radix(&name[1..], 16)
+            .ok()
+            .and_then(std::char::from_u32)
+            .unwrap_or('\u{fffd}'),
+        name => name.chars().next().unwrap(),
+    }
+}
+
+/// Completes the partial timestamps the Clojure reader accepts for #inst, like
+/// "2020" or "2020-01-01T10:30", to a full RFC 3339 timestamp, defaulting to
+/// the start of the period in UTC.
+pub fn inst(text: &str) -> Option<String> {
+    if !text.is_ascii() {
+        return None;
+    }
+    let tail = &text[text.len().saturating_sub(6)..];
+    let (base, offset) = match text.strip_suffix('Z') {
+        Some(base) => (base, "Z"),
+        None if text.len() >= 6 && tail.starts_with(&['+', '-'][..]) && tail.contains(':') => {
+            (&text[..text.len() - 6], tail)
+        }
+        None => (text, "Z"),
+    };
+    let full = match base.len() {
+        4 | 7 | 10 | 13 | 16 => {
+            format!("{}{}{}", base, &"0000-01-01T00:00:00"[base.len()..], offset)
+        }
+        _ => format!("{}{}", base, offset),
+    };
+    DateTime::parse_from_rfc3339(&full).ok().map(|_| full)
+}
+
+pub fn is_uuid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Names in namespaced maps get the namespace unless they already have one,
+/// `_/name` meaning no namespace.
+pub fn qualify(name: &str, ns: Option<&str>) -> String {
+    match ns {
+        Some(_) if name.starts_with("_/") => name[2..].to_string(),
+        Some(ns) if !name.contains('/') => format!("{}/{}", ns, name),
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(src: &str) -> String {
+        Reader::new(src).read_all().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn keeps_the_source() {
+        let forms = Reader::new("; top\n{:a 1, #_:b #_2 :c [\\space \"x\"]}\n\n#inst \"2020\"")
+            .read_all()
+            .unwrap();
+        assert_eq!(forms.len(), 3);
+        assert!(matches!(&forms[0].node, Node::Comment(text) if text == "; top"));
+        assert_eq!((forms[1].line, forms[1].col, forms[1].newlines), (2, 1, 1));
+        assert_eq!(forms[1].flat(), "{:a 1 :c [\\space \"x\"]}");
+        match &forms[1].node {
+            Node::Coll(Coll::Map, items) => {
+                assert_eq!(items.len(), 6);
+                assert!(matches!(&items[2].node, Node::Discard(_)));
+            }
+            node => panic!("{:?}", node),
+        }
+        assert_eq!(forms[2].newlines, 2);
+        assert_eq!(forms[2].flat(), "#inst \"2020\"");
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("[1 2"), "1:1: Unclosed [");
+        assert_eq!(
+            error("{:a 1}\n  (a]"),
+            "2:5: Unexpected ], expected ) to close ( from 2:3"
+        );
+        assert_eq!(error("[1]\n)"), "2:1: Unexpected )");
+        assert_eq!(error("\n\"a\\qb\""), "2:3: Invalid escape \\q in string");
+        assert_eq!(error("{:a}"), "1:1: Map with an odd number of forms");
+        assert_eq!(error("::a"), "1:1: Auto-resolved keyword ::a isn't EDN");
+        assert_eq!(error("[#_]"), "1:2: #_ without a form");
+        assert_eq!(error("1a"), "1:1: Invalid number 1a");
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(error("{:a 1\n :b 2\n :a 3}"), "3:2: Duplicate key :a");
+        assert_eq!(
+            error("#{[1 2] #_x [1\n2]}"),
+            "1:13: Duplicate set element [1 2]"
+        );
+        assert_eq!(error("#:x{:a 1 ; c\n :a 2}"), "2:2: Duplicate key :a");
+        assert!(Reader::new("{:a 1 #_:a 2}").read_all().is_err());
+        assert!(Reader::new("{:a 1 \"a\" 2 a 3 [:a] 4}").read_all().is_ok());
+    }
+
+    #[test]
+    fn depth() {
+        // the size of the main thread's stack, the binaries read there
+        let main = std::thread::Builder::new().stack_size(8 << 20);
+        let test = || {
+            let nested = |n| format!("{}{}", "[".repeat(n), "]".repeat(n));
+            assert!(Reader::new(&nested(MAX_DEPTH)).read_all().is_ok());
+            assert_eq!(
+                error(&nested(MAX_DEPTH + 1)),
+                format!("1:{}: Nested too deeply", MAX_DEPTH + 1)
+            );
+            assert_eq!(
+                error(&"#_".repeat(MAX_DEPTH + 1)),
+                format!("1:{}: Nested too deeply", MAX_DEPTH * 2 + 1)
+            );
+        };
+        main.spawn(test).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(unescape(r#""a\"b\né😀""#), "a\"b\né😀");
+        assert_eq!(escape("a\"b\\\n\u{1}"), r#""a\"b\\\n\u0001""#);
+        assert_eq!(unescape(&escape("tab\there")), "tab\there");
+        assert_eq!(character("\\newline"), '\n');
+        assert_eq!(character("\\u00e9"), 'é');
+        assert_eq!(number("42"), Some(Number::Int(42)));
+        assert_eq!(
+            number("18446744073709551615N"),
+            Some(Number::UInt(u64::MAX))
+        );
+        assert_eq!(number("99999999999999999999"), Some(Number::Big));
+        assert_eq!(number("1.5M"), Some(Number::Float(1.5)));
+        assert_eq!(number("1M"), Some(Number::Float(1.0)));
+    }
+
+    #[test]
+    fn insts() {
+        assert_eq!(inst("2020").as_deref(), Some("2020-01-01T00:00:00Z"));
+        assert_eq!(
+            inst("2020-03-04T10:30").as_deref(),
+            Some("2020-03-04T10:30:00Z")
+        );
+        assert_eq!(
+            inst("2020-03-04T10:30:00+01:00").as_deref(),
+            Some("2020-03-04T10:30:00+01:00")
+        );
+        assert_eq!(inst("2020-13"), None);
+        assert_eq!(inst("+1:00"), None);
+        assert_eq!(inst("-1:0"), None);
+        assert_eq!(inst("é"), None);
+    }
+}
@@ -0,0 +1,4 @@
+//! Code shared by the binaries in `src/bin`.
+
+pub mod edn_reader;
+pub mod serde_edn;
@@ -0,0 +1,939 @@
+//! Serde support for EDN on top of the reader in `edn_reader`.
+//!
+//! Struct fields and map keys are keywords, so `{:host "localhost" :port 7888}`
+//! deserializes into a struct with `host` and `port` fields. Clojure style
+//! names work with `#[serde(rename_all = "kebab-case")]` and namespaced ones
+//! with `#[serde(rename = "mvn/version")]`. Enums are read from keywords,
+//! symbols or strings naming the variant, from single entry maps like
+//! `{:variant value}` and from tagged values like `#variant value`, and unit
+//! variants are written as keywords. Other tagged values read as the value
+//! they tag, so `#inst` deserializes into `chrono::DateTime` and `#uuid` into
+//! strings. Use the `inst` and `uuid` modules with `#[serde(with = ..)]` to
+//! also write them tagged.
+
+use crate::edn_reader::{self, Coll, Form, Node, Number, Reader};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt;
+use std::vec;
+
+const INST: &str = "$edn::inst";
+const UUID: &str = "$edn::uuid";
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Reads the single EDN value of `s` into a `T`. Syntax errors are reported
+/// with their line and column.
+pub fn from_str<T: DeserializeOwned>(s: &str) -> Result<T> {
+    let forms = Reader::new(s)
+        .read_all()
+        .map_err(|e| Error(e.to_string()))?;
+    let mut values = forms.iter().filter(|form| form.is_value());
+    match (values.next(), values.next()) {
+        (Some(form), None) => from_form(form),
+        (Some(_), Some(form)) => Err(Error(format!(
+            "{}:{}: Unexpected form after the value",
+            form.line, form.col
+        ))),
+        (None, _) => Err(Error("No EDN found".into())),
+    }
+}
+
+pub fn from_form<'de, T: de::Deserialize<'de>>(form: &'de Form) -> Result<T> {
+    T::deserialize(Deserializer { form, ns: None })
+}
+
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let mut serializer = Serializer { out: String::new() };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.out)
+}
+
+/// `chrono::DateTime` as `#inst "..."`.
+pub mod inst {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, Tz: TimeZone>(
+        time: &DateTime<Tz>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        serializer.serialize_newtype_struct(super::INST, &time.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&time)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Strings as `#uuid "..."`.
+pub mod uuid {
+    use crate::edn_reader::is_uuid;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(uuid: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(super::UUID, uuid)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        let uuid = String::deserialize(deserializer)?;
+        if !is_uuid(&uuid) {
+            return Err(de::Error::custom(format!("invalid uuid '{}'", uuid)));
+        }
+        Ok(uuid.to_lowercase())
+    }
+}
+
+/// The values among the forms of a collection, without comments and
+/// discarded forms.
+fn values(forms: &[Form]) -> vec::IntoIter<&Form> {
+    forms
+        .iter()
+        .filter(|form| form.is_value())
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// The form a tag applies to, the reader puts it last.
+fn operand(forms: &[Form]) -> &Form {
+    forms.last().expect("tag without a form")
+}
+
+fn number<'de, V: Visitor<'de>>(text: &str, visitor: V) -> Result<V::Value> {
+    match edn_reader::number(text) {
+        Some(Number::Int(n)) => visitor.visit_i64(n),
+        Some(Number::UInt(n)) => visitor.visit_u64(n),
+        Some(Number::Float(f)) => visitor.visit_f64(f),
+        Some(Number::Big) => Err(Error(format!("Integer {} is too big", text))),
+        None => Err(Error(format!("Invalid number {}", text))),
+    }
+}
+
+/// Deserializes a form, `ns` being the namespace of the map when the form is
+/// a key of a namespaced map.
+#[derive(Clone, Copy)]
+pub struct Deserializer<'de> {
+    form: &'de Form,
+    ns: Option<&'de str>,
+}
+
+impl<'de> Deserializer<'de> {
+    fn unexpected(&self) -> de::Unexpected<'de> {
+        match &self.form.node {
+            Node::Atom(text) => match text.as_str() {
+                "nil" => de::Unexpected::Unit,
+                "true" => de::Unexpected::Bool(true),
+                "false" => de::Unexpected::Bool(false),
+                text if text.starts_with('"') => de::Unexpected::Other("string"),
+                text if text.starts_with('\\') => de::Unexpected::Other("character"),
+                text if text.starts_with(':') => de::Unexpected::Other("keyword"),
+                text if edn_reader::is_number(text) || text.starts_with("##") => {
+                    de::Unexpected::Other("number")
+                }
+                _ => de::Unexpected::Other("symbol"),
+            },
+            Node::Coll(Coll::Map, _) | Node::NsMap(_, _) => de::Unexpected::Map,
+            Node::Coll(_, _) => de::Unexpected::Seq,
+            Node::Tagged(_, _) => de::Unexpected::Other("tagged value"),
+            Node::Discard(_) | Node::Comment(_) => de::Unexpected::Other("comment"),
+        }
+    }
+
+    /// The text of strings, keywords and symbols.
+    fn name(&self) -> Option<String> {
+        match &self.form.node {
+            Node::Atom(text) if text.starts_with('"') => Some(edn_reader::unescape(text)),
+            Node::Atom(text) if text.starts_with(':') => Some(text[1..].to_string()),
+            Node::Atom(text)
+                if edn_reader::is_symbol(text)
+                    && !["nil", "true", "false"].contains(&text.as_str()) =>
+            {
+                Some(text.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn atom<V: Visitor<'de>>(self, text: &'de str, visitor: V) -> Result<V::Value> {
+        match text {
+            "nil" => visitor.visit_unit(),
+            "true" => visitor.visit_bool(true),
+            "false" => visitor.visit_bool(false),
+            "##Inf" => visitor.visit_f64(f64::INFINITY),
+            "##-Inf" => visitor.visit_f64(f64::NEG_INFINITY),
+            "##NaN" => visitor.visit_f64(f64::NAN),
+            _ if text.starts_with('"') => visitor.visit_string(edn_reader::unescape(text)),
+            _ if text.starts_with('\\') => visitor.visit_char(edn_reader::character(text)),
+            _ if edn_reader::is_number(text) => number(text, visitor),
+            _ => {
+                let name = text.strip_prefix(':').unwrap_or(text);
+                match self.ns {
+                    Some(_) => visitor.visit_string(edn_reader::qualify(name, self.ns)),
+                    None => visitor.visit_borrowed_str(name),
+                }
+            }
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match &self.form.node {
+            Node::Atom(text) => self.atom(text, visitor),
+            Node::Coll(Coll::Map, forms) => visitor.visit_map(Map {
+                iter: values(forms),
+                ns: None,
+                value: None,
+            }),
+            Node::NsMap(ns, forms) => visitor.visit_map(Map {
+                iter: values(forms),
+                ns: Some(ns),
+                value: None,
+            }),
+            Node::Coll(_, forms) => visitor.visit_seq(Seq(values(forms))),
+            Node::Tagged(tag, forms) if tag == "inst" => {
+                let form = operand(forms);
+                match &form.node {
+                    Node::Atom(text) if text.starts_with('"') => {
+                        let text = edn_reader::unescape(text);
+                        match edn_reader::inst(&text) {
+                            Some(inst) => visitor.visit_string(inst),
+                            None => Err(Error(format!(
+                                "{}:{}: Invalid #inst \"{}\"",
+                                form.line, form.col, text
+                            ))),
+                        }
+                    }
+                    _ => Err(Error(format!(
+                        "{}:{}: #inst needs a string",
+                        form.line, form.col
+                    ))),
+                }
+            }
+            Node::Tagged(_, forms) => Deserializer {
+                form: operand(forms),
+                ns: None,
+            }
+            .deserialize_any(visitor),
+            Node::Discard(_) | Node::Comment(_) => {
+                Err(de::Error::invalid_type(self.unexpected(), &"a value"))
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match &self.form.node {
+            Node::Atom(text) if text == "nil" => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match &self.form.node {
+            Node::Atom(_) => match self.name() {
+                Some(name) => {
+                    visitor.visit_enum(IntoDeserializer::<Error>::into_deserializer(name))
+                }
+                None => Err(de::Error::invalid_type(self.unexpected(), &"a variant")),
+            },
+            Node::Tagged(tag, forms) => visitor.visit_enum(Enum {
+                variant: tag.clone(),
+                value: operand(forms),
+            }),
+            Node::Coll(Coll::Map, forms) => {
+                let mut entry = values(forms);
+                match (entry.next(), entry.next(), entry.next()) {
+                    (Some(key), Some(value), None) => {
+                        let key = Deserializer {
+                            form: key,
+                            ns: None,
+                        };
+                        match key.name() {
+                            Some(variant) => visitor.visit_enum(Enum { variant, value }),
+                            None => {
+                                Err(de::Error::invalid_type(key.unexpected(), &"a variant name"))
+                            }
+                        }
+                    }
+                    _ => Err(de::Error::invalid_type(self.unexpected(), &"a variant")),
+                }
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"a variant")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct Seq<'de>(vec::IntoIter<&'de Form>);
+
+impl<'de> de::SeqAccess<'de> for Seq<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>> {
+        match self.0.next() {
+            Some(form) => seed.deserialize(Deserializer { form, ns: None }).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The values of a map alternate between keys and values.
+struct Map<'de> {
+    iter: vec::IntoIter<&'de Form>,
+    ns: Option<&'de str>,
+    value: Option<&'de Form>,
+}
+
+impl<'de> de::MapAccess<'de> for Map<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.iter.next() {
+            Some(form) => {
+                self.value = self.iter.next();
+                seed.deserialize(Deserializer { form, ns: self.ns })
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        match self.value.take() {
+            Some(form) => seed.deserialize(Deserializer { form, ns: None }),
+            None => Err(de::Error::custom("value is missing")),
+        }
+    }
+}
+
+struct Enum<'de> {
+    variant: String,
+    value: &'de Form,
+}
+
+impl<'de> de::EnumAccess<'de> for Enum<'de> {
+    type Error = Error;
+    type Variant = Deserializer<'de>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant)> {
+        let variant =
+            seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.variant))?;
+        Ok((
+            variant,
+            Deserializer {
+                form: self.value,
+                ns: None,
+            },
+        ))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match &self.form.node {
+            Node::Atom(text) if text == "nil" => Ok(()),
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"nil")),
+        }
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+/// Writes EDN text in the style of `pr-str`.
+pub struct Serializer {
+    out: String,
+}
+
+impl Serializer {
+    fn keyword(&mut self, name: &str) {
+        self.out.push(':');
+        self.out.push_str(name);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push_str(&edn_reader::escape(s));
+    }
+
+    fn float(&mut self, f: f64) {
+        if f.is_nan() {
+            self.out.push_str("##NaN");
+        } else if f.is_infinite() {
+            self.out.push_str(if f > 0.0 { "##Inf" } else { "##-Inf" });
+        } else {
+            let text = f.to_string();
+            self.out.push_str(&text);
+            if !text.contains('.') {
+                self.out.push_str(".0");
+            }
+        }
+    }
+
+    fn compound(&mut self, open: &str, close: &'static str) -> Compound<'_> {
+        self.out.push_str(open);
+        Compound {
+            ser: self,
+            first: true,
+            close,
+        }
+    }
+}
+
+pub struct Compound<'a> {
+    ser: &'a mut Serializer,
+    first: bool,
+    close: &'static str,
+}
+
+impl<'a> Compound<'a> {
+    fn separate(&mut self, separator: &str) {
+        if !self.first {
+            self.ser.out.push_str(separator);
+        }
+        self.first = false;
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.separate(" ");
+        value.serialize(&mut *self.ser)
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        self.separate(", ");
+        self.ser.keyword(key);
+        self.ser.out.push(' ');
+        value.serialize(&mut *self.ser)
+    }
+
+    fn close(self) -> Result<()> {
+        self.ser.out.push_str(self.close);
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.out.push_str(if v { "true" } else { "false" });
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.out.push_str(&v.to_string());
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.out.push_str(&v.to_string());
+        if v > i64::MAX as u64 {
+            self.out.push('N');
+        }
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.float(v.into());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.float(v);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        match v {
+            '\n' => self.out.push_str("\\newline"),
+            '\r' => self.out.push_str("\\return"),
+            ' ' => self.out.push_str("\\space"),
+            '\t' => self.out.push_str("\\tab"),
+            c if c.is_whitespace() || c.is_control() => {
+                self.out.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => {
+                self.out.push('\\');
+                self.out.push(c);
+            }
+        }
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.string(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        let mut seq = self.compound("[", "]");
+        for byte in v {
+            seq.element(byte)?;
+        }
+        seq.close()
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.out.push_str("nil");
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.keyword(variant);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        match name {
+            INST => self.out.push_str("#inst "),
+            UUID => self.out.push_str("#uuid "),
+            _ => {}
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        let mut map = self.compound("{", "}");
+        map.field(variant, value)?;
+        map.close()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'a>> {
+        Ok(self.compound("[", "]"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.compound("[", "]"))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.compound("[", "]"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>> {
+        self.out.push('{');
+        self.keyword(variant);
+        Ok(self.compound(" [", "]}"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'a>> {
+        Ok(self.compound("{", "}"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'a>> {
+        Ok(self.compound("{", "}"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'a>> {
+        self.out.push('{');
+        self.keyword(variant);
+        Ok(self.compound(" {", "}}"))
+    }
+}
+
+impl<'a> ser::SerializeSeq for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+impl<'a> ser::SerializeTuple for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+impl<'a> ser::SerializeMap for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.separate(", ");
+        key.serialize(&mut *self.ser)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.ser.out.push(' ');
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+impl<'a> ser::SerializeStruct for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for Compound<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Everything {
+        name: String,
+        count: i64,
+        ratio: f64,
+        enabled: bool,
+        missing: Option<String>,
+        initial: char,
+        tags: Vec<String>,
+        sizes: BTreeMap<String, u64>,
+    }
+
+    #[test]
+    fn struct_round_trip() {
+        let value = Everything {
+            name: "a \"quoted\"\nline".into(),
+            count: -42,
+            ratio: 1.5,
+            enabled: true,
+            missing: None,
+            initial: ' ',
+            tags: vec!["x".into(), "y".into()],
+            sizes: vec![("big".to_string(), u64::MAX)].into_iter().collect(),
+        };
+        let text = to_string(&value).unwrap();
+        assert_eq!(
+            text,
+            "{:name \"a \\\"quoted\\\"\\nline\", :count -42, :ratio 1.5, :enabled true, \
+             :missing nil, :initial \\space, :tags [\"x\" \"y\"], \
+             :sizes {\"big\" 18446744073709551615N}}"
+        );
+        assert_eq!(from_str::<Everything>(&text).unwrap(), value);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Profile {
+        default_profile: String,
+        history_size: usize,
+        #[serde(rename = "mvn/version")]
+        version: String,
+    }
+
+    #[test]
+    fn kebab_case_fields() {
+        let profile: Profile = from_str(
+            "; comment\n{:default-profile dev, #_:ignored #_1 :history-size 10 \
+             :mvn/version \"1.10.1\"}",
+        )
+        .unwrap();
+        assert_eq!(
+            profile,
+            Profile {
+                default_profile: "dev".into(),
+                history_size: 10,
+                version: "1.10.1".into(),
+            }
+        );
+        let profile: Profile =
+            from_str("#:mvn{:version \"1.0\" :_/default-profile :x :_/history-size 1}").unwrap();
+        assert_eq!(profile.version, "1.0");
+        assert_eq!(profile.default_profile, "x");
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { width: i64, height: i64 },
+    }
+
+    #[test]
+    fn enums() {
+        let shapes: Vec<Shape> = from_str(
+            "[:point point \"point\" {:circle 2.0} #circle 3.0 #rect {:width 1 :height 2}]",
+        )
+        .unwrap();
+        assert_eq!(
+            shapes,
+            vec![
+                Shape::Point,
+                Shape::Point,
+                Shape::Point,
+                Shape::Circle(2.0),
+                Shape::Circle(3.0),
+                Shape::Rect {
+                    width: 1,
+                    height: 2
+                },
+            ]
+        );
+        let text = to_string(&shapes[3..]).unwrap();
+        assert_eq!(
+            text,
+            "[{:circle 2.0} {:circle 3.0} {:rect {:width 1, :height 2}}]"
+        );
+        assert_eq!(from_str::<Vec<Shape>>(&text).unwrap(), &shapes[3..]);
+        assert!(from_str::<Shape>(":square").is_err());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "inst")]
+        at: DateTime<Utc>,
+        #[serde(with = "uuid")]
+        id: String,
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn inst() {
+        let at: DateTime<Utc> = from_str("#inst \"2020-01-02T03:04:05+02:00\"").unwrap();
+        assert_eq!(at, utc("2020-01-02T01:04:05Z"));
+        let at: DateTime<Utc> = from_str("#inst \"2020-03\"").unwrap();
+        assert_eq!(at, utc("2020-03-01T00:00:00Z"));
+        assert!(from_str::<DateTime<Utc>>("#inst \"2020-13\"").is_err());
+        assert!(from_str::<DateTime<Utc>>("#inst 2020").is_err());
+    }
+
+    #[test]
+    fn uuid() {
+        let event = Event {
+            at: utc("2020-01-01T00:00:00Z"),
+            id: "f81d4fae-7dec-11d0-a765-00a0c91e6bf6".into(),
+        };
+        let text = to_string(&event).unwrap();
+        assert_eq!(
+            text,
+            "{:at #inst \"2020-01-01T00:00:00+00:00\", \
+             :id #uuid \"f81d4fae-7dec-11d0-a765-00a0c91e6bf6\"}"
+        );
+        assert_eq!(from_str::<Event>(&text).unwrap(), event);
+        let upper = "{:at #inst \"2020\" :id #uuid \"F81D4FAE-7DEC-11D0-A765-00A0C91E6BF6\"}";
+        assert_eq!(from_str::<Event>(upper).unwrap(), event);
+        assert!(from_str::<Event>("{:at #inst \"2020\" :id #uuid \"f81d4fae\"}").is_err());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let e = from_str::<Vec<i64>>("[1\n 2 @]").unwrap_err();
+        assert_eq!(e.to_string(), "2:4: Invalid symbol @");
+        let e = from_str::<i64>("1 2").unwrap_err();
+        assert_eq!(e.to_string(), "1:3: Unexpected form after the value");
+        assert!(from_str::<i64>("; nothing").is_err());
+    }
+}